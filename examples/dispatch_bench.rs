@@ -0,0 +1,24 @@
+//! Size of the bytecode and dispatch speed of the VM on a hot loop
+//!
+//! cargo run --release --example dispatch_bench
+
+use std::time::Instant;
+
+use tinyjs::vm;
+
+fn main() {
+    let source = "var i = 0; var s = 0; var name = 'counter'; while (i < 1000000) { s = s + i * 2; if (s > 1000) { s = s - 1000; } i++; }";
+    let bc = tinyjs::compile_source(source);
+    println!(
+        "size_of::<OpCode>() = {}",
+        std::mem::size_of::<vm::OpCode>()
+    );
+    println!("instructions        = {}", bc.instructions.len());
+    println!("constants           = {}", bc.constants.len());
+    println!("names               = {}", bc.names.len());
+    let start = Instant::now();
+    let mut machine = vm::VM::new(bc);
+    machine.run().unwrap();
+    let elapsed = start.elapsed();
+    println!("run                 = {:?}", elapsed);
+}
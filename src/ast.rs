@@ -1,4 +1,4 @@
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Expr {
     Identifier(String),
     Literal(Literal),
@@ -48,7 +48,7 @@ pub enum Expr {
     Function(Function),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Literal {
     Null,
     Undefined,
@@ -59,14 +59,14 @@ pub enum Literal {
     Object(Vec<(PropertyKey, Expr)>), // { a: 1, b: 2 }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum PropertyKey {
     Identifier(String),
     String(String),
     Number(f64),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum BinOp {
     Add,
     Sub,
@@ -90,7 +90,7 @@ pub enum BinOp {
     In,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum UnaryOp {
     Pos,
     Neg,
@@ -101,7 +101,7 @@ pub enum UnaryOp {
     Delete,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Stmt {
     Block(Vec<Stmt>),
     Var(Vec<(String, Option<Expr>)>),
@@ -116,6 +116,10 @@ pub enum Stmt {
         cond: Expr,
         body: Box<Stmt>,
     },
+    DoWhile {
+        body: Box<Stmt>,
+        cond: Expr,
+    },
     For {
         init: Option<ForInit>,
         cond: Option<Expr>,
@@ -127,35 +131,61 @@ pub enum Stmt {
         expr: Expr,
         body: Box<Stmt>,
     },
-    Continue,
-    Break,
+    Continue(Option<String>), // continue label;
+    Break(Option<String>),
+    Labeled(String, Box<Stmt>), // label: statement
     Return(Option<Expr>),
     With {
         expr: Expr,
         body: Box<Stmt>,
     },
     Function(Function),
+    Throw(Expr),
+    Try {
+        block: Box<Stmt>,
+        catch: Option<(String, Box<Stmt>)>, // catch (name) { ... }
+        finally: Option<Box<Stmt>>,
+    },
+    Switch {
+        discriminant: Expr,
+        cases: Vec<SwitchCase>,
+    },
+    Spanned(Span, Box<Stmt>), // position of the statement in the source
 }
 
-#[derive(PartialEq, Debug)]
+// 1-based position of the first token of a statement
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum ForInit {
     Var(Vec<(String, Option<Expr>)>),
     Expr(Expr),
 }
 
-#[derive(PartialEq, Debug)]
+// `case test: body`, no test for `default: body`
+#[derive(PartialEq, Debug, Clone)]
+pub struct SwitchCase {
+    pub test: Option<Expr>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub struct Function {
     pub name: Option<String>,
     pub params: Vec<String>,
     pub body: Vec<Stmt>,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Program {
     pub body: Vec<Stmt>,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum AssignOp {
     Assign,       // =
     AddAssign,    // +=
@@ -171,7 +201,7 @@ pub enum AssignOp {
     BitXorAssign, // ^=
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum UpdateOp {
     Inc, // ++
     Dec, // --
@@ -1,7 +1,7 @@
 /*
  * TinyJS's IR is a simple Three-Adress Code-ish. Here are cool features:
  *  - 'Label' as a function: easier to optimize jumps and constant folding
- *  - Arrays are dynamic and are used to represent objects and arrays (you can delete a key by setting it to Undefined)
+ *  - Arrays are dynamic and are used to represent objects and arrays
  *  - Prototypes properties are injected in an object by the AST->IR compiler
  *
  *  The AST->IR compiler implement most of the rules of ES1 seen in the standard paper
@@ -18,6 +18,13 @@
 use crate::ast;
 use crate::error;
 
+pub mod cfg;
+pub mod dce;
+pub mod ssa;
+pub mod validate;
+
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Operand {
     Var(String),  // reference to another variable
@@ -41,7 +48,7 @@ pub enum Const {
 
 // A Function take one or two arguments (Nodes) and
 // assign its result to the target node in an instruction
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Function {
     Noop(Operand), // return the operand
     Add(Operand, Operand),
@@ -53,14 +60,18 @@ pub enum Function {
     Inv(Operand), // return the inverse of a boolean (true -> false ; false -> true)
     Equal(Operand, Operand), // a == b
     NotEqual(Operand, Operand), // a != b
+    StrictEqual(Operand, Operand), // same type and value, without conversion (switch cases)
     LessThan(Operand, Operand), // a < b
     GreaterThan(Operand, Operand), // a > b
     LessThanEqual(Operand, Operand), // a <= b
     GreaterThanEqual(Operand, Operand), // a >= b
+    In(Operand, Operand), // a in b: does the object b have the property a
+    GetProp(Operand, Operand), // a[b]: read the property b of the object a
+    Typeof(Operand), // type of the operand as a string, "undefined" for an undeclared var
 }
 
 // Functions that do not return anything
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SoloFunction {
     Label(i64), // create a "target" for jumps ; the "key" is it's only argument, must be a Number
     JumpIf(Operand, i64), // jump to a label if the argument is true, only accept Boolean
@@ -69,18 +80,27 @@ pub enum SoloFunction {
     FnStart(String, i64), // declare the start of a function block (string = name, i64 = number of arguments)
     FnEnd(),              // end a function block
     Return(Option<Operand>),
-    PushToScope(Operand),        // push an object in scope chain (for `with`)
-    PopFromScope(),              // pop last pushed object from scope chain
-    FnCall(String, Operand),     // Call a global function by name: fn(args)
+    PushToScope(Operand),    // push an object in scope chain (for `with`)
+    PopFromScope(),          // pop last pushed object from scope chain
+    FnCall(String, Operand), // Call a global function by name: fn(args)
     MethodCall(Operand, String, Operand), // Call a method on object: obj.method(args)
-    Call(Operand, Operand),      // Call the first operand as a function
+    Call(Operand, Operand),  // Call the first operand as a function
+    CallResult(String),      // store the value returned by the call just before in a var
     ForInStart(String, Operand), // create an iterator on an object
-    ForInNext(String, Operand),  // get the next key of the object
+    ForInNext(String, Operand), // get the next key of the object
     MakeObject(String, Vec<(String, Operand)>), // Create object and assign to variable: MakeObject(var_name, [(key, value), ...])
     MakeArray(String, Vec<Operand>), // Create array and assign to variable: MakeArray(var_name, [elements, ...])
+    Position(u32, u32), // the next instructions come from this line and column of the source
+    TryStart(i64),      // install a handler: an exception raised from here jumps to the label
+    TryEnd(),           // remove the last installed handler
+    Catch(String),      // first instruction of a handler: store the exception in a var
+    Throw(Operand),     // raise the operand as an exception
+    Delete(String, Operand, Operand), // var = delete obj[key], true unless the property is DontDelete
+    DeleteVar(String, String),        // var = delete name, false for declared variables
+    SetProp(Operand, Operand, Operand), // obj[key] = value
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Instruction {
     Assign {
         // a = b
@@ -97,10 +117,262 @@ pub enum Instruction {
     },
 }
 
-// Store a label to jump at when we encouter a continue or a break
+impl Function {
+    pub fn operands(&self) -> Vec<&Operand> {
+        use Function::*;
+        match self {
+            Noop(a) | Inv(a) | Typeof(a) => vec![a],
+            Add(a, b)
+            | Sub(a, b)
+            | Mul(a, b)
+            | Mod(a, b)
+            | Div(a, b)
+            | Pow(a, b)
+            | Equal(a, b)
+            | NotEqual(a, b)
+            | StrictEqual(a, b)
+            | LessThan(a, b)
+            | GreaterThan(a, b)
+            | LessThanEqual(a, b)
+            | GreaterThanEqual(a, b)
+            | In(a, b)
+            | GetProp(a, b) => vec![a, b],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        use Function::*;
+        match self {
+            Noop(a) | Inv(a) | Typeof(a) => vec![a],
+            Add(a, b)
+            | Sub(a, b)
+            | Mul(a, b)
+            | Mod(a, b)
+            | Div(a, b)
+            | Pow(a, b)
+            | Equal(a, b)
+            | NotEqual(a, b)
+            | StrictEqual(a, b)
+            | LessThan(a, b)
+            | GreaterThan(a, b)
+            | LessThanEqual(a, b)
+            | GreaterThanEqual(a, b)
+            | In(a, b)
+            | GetProp(a, b) => vec![a, b],
+        }
+    }
+}
+
+impl Instruction {
+    // Variable written by the instruction, if any
+    pub fn dest(&self) -> Option<&str> {
+        match self {
+            Instruction::Assign { dest, .. } | Instruction::Classic { dest, .. } => Some(dest),
+            Instruction::Call { function } => match function {
+                SoloFunction::ForInStart(dest, _)
+                | SoloFunction::ForInNext(dest, _)
+                | SoloFunction::MakeObject(dest, _)
+                | SoloFunction::MakeArray(dest, _)
+                | SoloFunction::Catch(dest)
+                | SoloFunction::Delete(dest, _, _)
+                | SoloFunction::DeleteVar(dest, _)
+                | SoloFunction::CallResult(dest) => Some(dest),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Instruction::Assign { dest, .. } | Instruction::Classic { dest, .. } => Some(dest),
+            Instruction::Call { function } => match function {
+                SoloFunction::ForInStart(dest, _)
+                | SoloFunction::ForInNext(dest, _)
+                | SoloFunction::MakeObject(dest, _)
+                | SoloFunction::MakeArray(dest, _)
+                | SoloFunction::Catch(dest)
+                | SoloFunction::Delete(dest, _, _)
+                | SoloFunction::DeleteVar(dest, _)
+                | SoloFunction::CallResult(dest) => Some(dest),
+                _ => None,
+            },
+        }
+    }
+
+    // Operands read by the instruction (Kill is not a read)
+    pub fn uses(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::Assign { src, .. } => vec![src],
+            Instruction::Classic { function, .. } => function.operands(),
+            Instruction::Call { function } => match function {
+                SoloFunction::JumpIf(cond, _) => vec![cond],
+                SoloFunction::Return(Some(op))
+                | SoloFunction::PushToScope(op)
+                | SoloFunction::Throw(op) => vec![op],
+                SoloFunction::FnCall(_, args) => vec![args],
+                SoloFunction::MethodCall(obj, _, args) => vec![obj, args],
+                SoloFunction::Call(func, args) => vec![func, args],
+                SoloFunction::ForInStart(_, obj) => vec![obj],
+                SoloFunction::ForInNext(_, iter) => vec![iter],
+                SoloFunction::MakeObject(_, props) => props.iter().map(|(_, v)| v).collect(),
+                SoloFunction::MakeArray(_, elements) => elements.iter().collect(),
+                SoloFunction::Delete(_, obj, key) => vec![obj, key],
+                SoloFunction::SetProp(obj, key, value) => vec![obj, key, value],
+                _ => vec![],
+            },
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Assign { src, .. } => vec![src],
+            Instruction::Classic { function, .. } => function.operands_mut(),
+            Instruction::Call { function } => match function {
+                SoloFunction::JumpIf(cond, _) => vec![cond],
+                SoloFunction::Return(Some(op))
+                | SoloFunction::PushToScope(op)
+                | SoloFunction::Throw(op) => vec![op],
+                SoloFunction::FnCall(_, args) => vec![args],
+                SoloFunction::MethodCall(obj, _, args) => vec![obj, args],
+                SoloFunction::Call(func, args) => vec![func, args],
+                SoloFunction::ForInStart(_, obj) => vec![obj],
+                SoloFunction::ForInNext(_, iter) => vec![iter],
+                SoloFunction::MakeObject(_, props) => props.iter_mut().map(|(_, v)| v).collect(),
+                SoloFunction::MakeArray(_, elements) => elements.iter_mut().collect(),
+                SoloFunction::Delete(_, obj, key) => vec![obj, key],
+                SoloFunction::SetProp(obj, key, value) => vec![obj, key, value],
+                _ => vec![],
+            },
+        }
+    }
+
+    // Label targeted by a Jump, a JumpIf or the handler of a TryStart
+    pub fn jump_target(&self) -> Option<i64> {
+        match self {
+            Instruction::Call {
+                function:
+                    SoloFunction::Jump(label)
+                    | SoloFunction::JumpIf(_, label)
+                    | SoloFunction::TryStart(label),
+            } => Some(*label),
+            _ => None,
+        }
+    }
+}
+
+// Textual form of the IR, used by debug outputs (CFG dumps, ...)
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Const::String(s) => write!(f, "{:?}", s),
+            Const::Number(n) => write!(f, "{}", n),
+            Const::Boolean(b) => write!(f, "{}", b),
+            Const::Undefined => write!(f, "undefined"),
+            Const::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Var(name) => write!(f, "{}", name),
+            Operand::Const(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Function::*;
+        let name = match self {
+            Noop(_) => "noop",
+            Add(..) => "add",
+            Sub(..) => "sub",
+            Mul(..) => "mul",
+            Mod(..) => "mod",
+            Div(..) => "div",
+            Pow(..) => "pow",
+            Inv(_) => "inv",
+            Equal(..) => "eq",
+            NotEqual(..) => "ne",
+            StrictEqual(..) => "stricteq",
+            LessThan(..) => "lt",
+            GreaterThan(..) => "gt",
+            LessThanEqual(..) => "le",
+            GreaterThanEqual(..) => "ge",
+            In(..) => "in",
+            GetProp(..) => "getprop",
+            Typeof(_) => "typeof",
+        };
+        let operands: Vec<String> = self.operands().iter().map(|op| op.to_string()).collect();
+        write!(f, "{} {}", name, operands.join(", "))
+    }
+}
+
+impl fmt::Display for SoloFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SoloFunction::*;
+        match self {
+            Label(id) => write!(f, "L{}:", id),
+            JumpIf(cond, id) => write!(f, "jumpif {}, L{}", cond, id),
+            Jump(id) => write!(f, "jump L{}", id),
+            Kill(op) => write!(f, "kill {}", op),
+            FnStart(name, argc) => write!(f, "fnstart {}/{}", name, argc),
+            FnEnd() => write!(f, "fnend"),
+            Return(Some(op)) => write!(f, "return {}", op),
+            Return(None) => write!(f, "return"),
+            PushToScope(op) => write!(f, "pushscope {}", op),
+            PopFromScope() => write!(f, "popscope"),
+            FnCall(name, args) => write!(f, "call {}({})", name, args),
+            MethodCall(obj, method, args) => write!(f, "call {}.{}({})", obj, method, args),
+            Call(func, args) => write!(f, "call {}({})", func, args),
+            CallResult(dest) => write!(f, "{} = result", dest),
+            ForInStart(iter, obj) => write!(f, "{} = forinstart {}", iter, obj),
+            ForInNext(key, iter) => write!(f, "{} = forinnext {}", key, iter),
+            MakeObject(name, props) => {
+                let props: Vec<String> =
+                    props.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{} = {{{}}}", name, props.join(", "))
+            }
+            MakeArray(name, elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "{} = [{}]", name, elements.join(", "))
+            }
+            Position(line, column) => write!(f, "pos {}:{}", line, column),
+            TryStart(id) => write!(f, "trystart L{}", id),
+            TryEnd() => write!(f, "tryend"),
+            Catch(name) => write!(f, "{} = catch", name),
+            Throw(op) => write!(f, "throw {}", op),
+            Delete(dest, obj, key) => write!(f, "{} = delete {}[{}]", dest, obj, key),
+            DeleteVar(dest, name) => write!(f, "{} = delete {}", dest, name),
+            SetProp(obj, key, value) => write!(f, "{}[{}] = {}", obj, key, value),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Assign { dest, src } => write!(f, "{} = {}", dest, src),
+            Instruction::Classic { dest, function } => write!(f, "{} = {}", dest, function),
+            Instruction::Call { function } => write!(f, "{}", function),
+        }
+    }
+}
+
+// Store a label to jump at when we encouter a continue or a break (a switch
+// has no continue label, a continue in it goes to the enclosing loop)
+//
+// A labeled statement has its own context, only used by `break label` and
+// `continue label`. When the statement is a loop, the context gets the continue
+// label of the loop once it's compiled.
 pub struct LoopContext {
-    continue_label: i64,
+    continue_label: Option<i64>,
     break_label: i64,
+    try_depth: usize, // try statements around the loop, the ones above are left by a jump
+    label: Option<String>,
+    labels_loop: bool, // the labeled statement is a loop
 }
 
 // Store a label to jump when we encouter a Return + the var to modify with
@@ -108,6 +380,14 @@ pub struct LoopContext {
 pub struct ReturnContext {
     label: i64,
     variable: &'static String,
+    try_depth: usize,
+}
+
+// A try statement we are in, with a handler installed in the VM. Jumping out of
+// it (break, continue, return) must remove the handler and run the finally block
+#[derive(Clone)]
+pub struct TryContext {
+    finally: Option<ast::Stmt>,
 }
 
 // AST -> IR
@@ -118,6 +398,14 @@ pub struct Compiler {
     pub label_stack: i64,
     pub loop_stack: Vec<LoopContext>,
     pub return_stack: Vec<ReturnContext>,
+    pub try_stack: Vec<TryContext>,
+}
+
+// Names made by Compiler::new_temporary: only the compiler reads and writes
+// them, a script can't refer to them
+pub fn is_temporary(name: &str) -> bool {
+    name.strip_prefix("__t")
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
 }
 
 impl Compiler {
@@ -128,7 +416,7 @@ impl Compiler {
         }
     }
 
-    fn error(&mut self, message: &'static str) -> ! {
+    fn error(&self, message: impl fmt::Display) -> ! {
         error::fail(format!("AST->IR Compiler error: {}", message));
     }
 
@@ -137,6 +425,82 @@ impl Compiler {
         self.label_stack
     }
 
+    // Fresh `__tN` variable holding an intermediate value, see is_temporary
+    fn new_temporary(&mut self) -> String {
+        format!("__t{}", self.new_label())
+    }
+
+    // Enter a loop or a switch, the labels right before it get its continue label
+    fn push_loop(&mut self, continue_label: Option<i64>, break_label: i64) {
+        for ctx in self.loop_stack.iter_mut().rev() {
+            if ctx.label.is_none() || ctx.continue_label.is_some() {
+                break;
+            }
+            if ctx.labels_loop {
+                ctx.continue_label = continue_label;
+            }
+        }
+        self.loop_stack.push(LoopContext {
+            continue_label,
+            break_label,
+            try_depth: self.try_stack.len(),
+            label: None,
+            labels_loop: false,
+        });
+    }
+
+    // Context targeted by a break or a continue: the labeled statement, or
+    // the innermost loop (or switch for a break)
+    fn jump_context(&self, label: Option<String>, is_continue: bool) -> &LoopContext {
+        let found = match &label {
+            Some(name) => self
+                .loop_stack
+                .iter()
+                .rposition(|ctx| ctx.label.as_ref() == Some(name)),
+            None => self.loop_stack.iter().rposition(|ctx| {
+                ctx.label.is_none() && (!is_continue || ctx.continue_label.is_some())
+            }),
+        };
+        let statement = if is_continue { "Continue" } else { "Break" };
+        match (found, label) {
+            (Some(i), Some(name)) if is_continue && self.loop_stack[i].continue_label.is_none() => {
+                self.error(format!("Continue target '{}' is not a loop", name))
+            }
+            (Some(i), _) => &self.loop_stack[i],
+            (None, Some(name)) => self.error(format!("Undefined label '{}'", name)),
+            (None, None) => self.error(format!("{} statement outside of loop", statement)),
+        }
+    }
+
+    // Leave the try statements entered since `depth` before jumping out of
+    // them: remove their handlers and inline their finally blocks, innermost
+    // first. A finally block still runs inside the try statements around it.
+    fn exit_try_blocks(&mut self, depth: usize) {
+        let saved = self.try_stack.clone();
+        while self.try_stack.len() > depth {
+            let ctx = self.try_stack.pop().unwrap();
+            self.output.body.push(Instruction::Call {
+                function: SoloFunction::TryEnd(),
+            });
+            if let Some(finally) = ctx.finally {
+                self.compile_stmt(finally);
+            }
+        }
+        self.try_stack = saved;
+    }
+
+    // Handler rethrowing the exception once the finally block has run
+    fn compile_rethrow(&mut self, finally: ast::Stmt) {
+        let exception = format!("__exc_{}", self.new_label());
+        self.output.body.push(Instruction::Call {
+            function: SoloFunction::Catch(exception.clone()),
+        });
+        self.compile_stmt(finally);
+        self.output.body.push(Instruction::Call {
+            function: SoloFunction::Throw(Operand::Var(exception)),
+        });
+    }
+
     // big switch statement
     fn compile_stmt(&mut self, s: ast::Stmt) -> () {
         match s {
@@ -199,12 +563,21 @@ impl Compiler {
                     self.return_stack.push(ReturnContext {
                         label: ret_label,
                         variable: ret_var,
+                        try_depth: self.try_stack.len(),
                     });
 
                     self.output.body.push(Instruction::Call {
                         function: SoloFunction::FnStart(name, func.params.len() as i64),
                     });
 
+                    // the VM passes the arguments as arg0, arg1...
+                    for (i, param) in func.params.into_iter().enumerate() {
+                        self.output.body.push(Instruction::Assign {
+                            dest: param,
+                            src: Operand::Var(format!("arg{}", i)),
+                        });
+                    }
+
                     for stmt in func.body {
                         self.compile_stmt(stmt);
                     }
@@ -261,10 +634,7 @@ impl Compiler {
                 let loop_start = self.new_label();
                 let loop_end = self.new_label();
 
-                self.loop_stack.push(LoopContext {
-                    continue_label: cond_label,
-                    break_label: loop_end,
-                });
+                self.push_loop(Some(cond_label), loop_end);
 
                 // Évaluation de la condition
                 self.output.body.push(Instruction::Call {
@@ -296,6 +666,33 @@ impl Compiler {
 
                 self.loop_stack.pop();
             }
+            ast::Stmt::DoWhile { body, cond } => {
+                let loop_start = self.new_label();
+                let cond_label = self.new_label();
+                let loop_end = self.new_label();
+
+                self.push_loop(Some(cond_label), loop_end);
+
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(loop_start),
+                });
+                self.compile_stmt(*body);
+
+                // continue evaluates the condition before going back
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(cond_label),
+                });
+                let cond_val = self.compile_expr(cond);
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::JumpIf(cond_val, loop_start),
+                });
+
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(loop_end),
+                });
+
+                self.loop_stack.pop();
+            }
             ast::Stmt::For {
                 init,
                 cond,
@@ -315,8 +712,12 @@ impl Compiler {
                 }
 
                 let loop_start = self.new_label();
+                let update_label = self.new_label();
                 let loop_end = self.new_label();
 
+                // continue runs the update before testing the condition again
+                self.push_loop(Some(update_label), loop_end);
+
                 self.output.body.push(Instruction::Call {
                     function: SoloFunction::Label(loop_start),
                 });
@@ -336,8 +737,11 @@ impl Compiler {
                     });
                 }
 
-                self.compile_stmt(*body); // break, continue and return are handled in there
+                self.compile_stmt(*body);
 
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(update_label),
+                });
                 if let Some(u) = update {
                     self.compile_expr(u); // unused result to trigger side effects
                 }
@@ -349,6 +753,8 @@ impl Compiler {
                 self.output.body.push(Instruction::Call {
                     function: SoloFunction::Label(loop_end),
                 });
+
+                self.loop_stack.pop();
             }
             ast::Stmt::ForIn { var, expr, body } => {
                 let obj_val = self.compile_expr(expr);
@@ -365,10 +771,7 @@ impl Compiler {
                 let loop_start = self.new_label();
                 let loop_end = self.new_label();
 
-                self.loop_stack.push(LoopContext {
-                    continue_label: loop_start,
-                    break_label: loop_end,
-                });
+                self.push_loop(Some(loop_start), loop_end);
 
                 self.output.body.push(Instruction::Call {
                     function: SoloFunction::Label(loop_start),
@@ -416,29 +819,80 @@ impl Compiler {
                 self.output.body.push(Instruction::Call {
                     function: SoloFunction::Kill(Operand::Var(done_var)),
                 });
+
+                self.loop_stack.pop();
             }
-            ast::Stmt::Continue => match self.loop_stack.last() {
-                Some(ctx) => {
-                    self.output.body.push(Instruction::Call {
-                        function: SoloFunction::Jump(ctx.continue_label),
-                    });
+            ast::Stmt::Continue(label) => {
+                let ctx = self.jump_context(label, true);
+                let (target, depth) = (ctx.continue_label.unwrap(), ctx.try_depth);
+                self.exit_try_blocks(depth);
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Jump(target),
+                });
+            }
+            ast::Stmt::Break(label) => {
+                let ctx = self.jump_context(label, false);
+                let (target, depth) = (ctx.break_label, ctx.try_depth);
+                self.exit_try_blocks(depth);
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Jump(target),
+                });
+            }
+            ast::Stmt::Labeled(label, body) => {
+                if self
+                    .loop_stack
+                    .iter()
+                    .any(|ctx| ctx.label.as_ref() == Some(&label))
+                {
+                    self.error(format!("Label '{}' is already declared", label));
                 }
-                None => self.error("Continue statement outside of loop"),
-            },
-            ast::Stmt::Break => match self.loop_stack.last() {
-                Some(ctx) => {
-                    self.output.body.push(Instruction::Call {
-                        function: SoloFunction::Jump(ctx.break_label),
-                    });
+                let mut inner = body.as_ref();
+                while let ast::Stmt::Spanned(_, stmt) | ast::Stmt::Labeled(_, stmt) = inner {
+                    inner = stmt.as_ref();
                 }
-                None => self.error("Break statement outside of loop"),
-            },
+                let labels_loop = matches!(
+                    inner,
+                    ast::Stmt::While { .. }
+                        | ast::Stmt::DoWhile { .. }
+                        | ast::Stmt::For { .. }
+                        | ast::Stmt::ForIn { .. }
+                );
+
+                let end = self.new_label();
+                self.loop_stack.push(LoopContext {
+                    continue_label: None,
+                    break_label: end,
+                    try_depth: self.try_stack.len(),
+                    label: Some(label),
+                    labels_loop,
+                });
+                self.compile_stmt(*body);
+                self.loop_stack.pop();
+
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(end),
+                });
+            }
             ast::Stmt::Return(xpr) => {
-                let expr_val = match xpr {
+                let mut expr_val = match xpr {
                     Some(expr) => self.compile_expr(expr),
                     None => Operand::Const(Const::Undefined),
                 };
 
+                let depth = self.return_stack.last().map_or(0, |ctx| ctx.try_depth);
+                if self.try_stack.len() > depth {
+                    // the value is computed before the finally blocks run
+                    if let Operand::Var(_) = expr_val {
+                        let temp = self.new_temporary();
+                        self.output.body.push(Instruction::Assign {
+                            dest: temp.clone(),
+                            src: expr_val,
+                        });
+                        expr_val = Operand::Var(temp);
+                    }
+                    self.exit_try_blocks(depth);
+                }
+
                 self.output.body.push(Instruction::Call {
                     function: SoloFunction::Return(Some(expr_val.clone())),
                 });
@@ -471,7 +925,155 @@ impl Compiler {
                 });
             }
 
+            ast::Stmt::Throw(expr) => {
+                let expr_val = self.compile_expr(expr);
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Throw(expr_val),
+                });
+            }
+            // try { A } catch (e) { B } finally { F } becomes:
+            //      trystart L1; A; tryend; F; jump L3
+            //  L1: e = catch; trystart L2; B; tryend; F; jump L3
+            //  L2: exc = catch; F; throw exc
+            //  L3:
+            // the catch variable is a plain variable of the enclosing function
+            ast::Stmt::Try {
+                block,
+                catch,
+                finally,
+            } => {
+                let handler = self.new_label();
+                let end = self.new_label();
+                let finally = finally.map(|f| *f);
+
+                self.try_stack.push(TryContext {
+                    finally: finally.clone(),
+                });
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::TryStart(handler),
+                });
+                self.compile_stmt(*block);
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::TryEnd(),
+                });
+                self.try_stack.pop();
+                if let Some(f) = &finally {
+                    self.compile_stmt(f.clone());
+                }
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Jump(end),
+                });
+
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(handler),
+                });
+                match (catch, finally) {
+                    (Some((name, body)), None) => {
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::Catch(name),
+                        });
+                        self.compile_stmt(*body);
+                    }
+                    (Some((name, body)), Some(f)) => {
+                        let rethrow = self.new_label();
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::Catch(name),
+                        });
+                        self.try_stack.push(TryContext {
+                            finally: Some(f.clone()),
+                        });
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::TryStart(rethrow),
+                        });
+                        self.compile_stmt(*body);
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::TryEnd(),
+                        });
+                        self.try_stack.pop();
+                        self.compile_stmt(f.clone());
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::Jump(end),
+                        });
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::Label(rethrow),
+                        });
+                        self.compile_rethrow(f);
+                    }
+                    (None, Some(f)) => self.compile_rethrow(f),
+                    (None, None) => self.error("Try statement without catch or finally"),
+                }
+
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(end),
+                });
+            }
+            // the tests are checked in order, then the bodies are laid out in
+            // the source order so a case without break falls through the next.
+            // A case matches without conversion, "1" doesn't match 1:
+            //      d = discriminant
+            //      jumpif d === test0, L0; jumpif d === test1, L1; jump Ldefault
+            //  L0: body0
+            //  L1: body1
+            //  Ldefault: ...
+            //  Lend:
+            ast::Stmt::Switch {
+                discriminant,
+                cases,
+            } => {
+                let discriminant = self.compile_expr(discriminant);
+                let value = self.new_temporary();
+                self.output.body.push(Instruction::Assign {
+                    dest: value.clone(),
+                    src: discriminant,
+                });
+
+                let end = self.new_label();
+                let labels: Vec<i64> = cases.iter().map(|_| self.new_label()).collect();
+                let (tests, bodies): (Vec<_>, Vec<_>) =
+                    cases.into_iter().map(|case| (case.test, case.body)).unzip();
+                let mut default = end;
+
+                for (test, &label) in tests.into_iter().zip(&labels) {
+                    let Some(test) = test else {
+                        default = label;
+                        continue;
+                    };
+                    let test = self.compile_expr(test);
+                    let matched = self.new_temporary();
+                    self.output.body.push(Instruction::Classic {
+                        dest: matched.clone(),
+                        function: Function::StrictEqual(Operand::Var(value.clone()), test),
+                    });
+                    self.output.body.push(Instruction::Call {
+                        function: SoloFunction::JumpIf(Operand::Var(matched), label),
+                    });
+                }
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Jump(default),
+                });
+
+                self.push_loop(None, end);
+                for (body, label) in bodies.into_iter().zip(labels) {
+                    self.output.body.push(Instruction::Call {
+                        function: SoloFunction::Label(label),
+                    });
+                    for stmt in body {
+                        self.compile_stmt(stmt);
+                    }
+                }
+                self.loop_stack.pop();
+
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Label(end),
+                });
+            }
             ast::Stmt::Empty => {}
+            ast::Stmt::Spanned(span, stmt) => {
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::Position(span.line, span.column),
+                });
+                self.compile_stmt(*stmt);
+            }
         }
     }
 
@@ -485,7 +1087,7 @@ impl Compiler {
                 ast::Literal::Null => Operand::Const(Const::Null),
                 ast::Literal::Undefined => Operand::Const(Const::Undefined),
                 ast::Literal::Array(elements) => {
-                    let array_var = format!("__t{}", self.new_label());
+                    let array_var = self.new_temporary();
                     let mut arr_elements = Vec::new();
                     for elem in elements {
                         let val = self.compile_expr(elem);
@@ -497,7 +1099,7 @@ impl Compiler {
                     Operand::Var(array_var)
                 }
                 ast::Literal::Object(props) => {
-                    let obj_var = format!("__t{}", self.new_label());
+                    let obj_var = self.new_temporary();
                     let mut obj_props = Vec::new();
                     for (key, value) in props {
                         let prop_name = match key {
@@ -517,7 +1119,7 @@ impl Compiler {
             ast::Expr::Binary { op, left, right } => {
                 let l = self.compile_expr(*left);
                 let r = self.compile_expr(*right);
-                let dest = format!("__t{}", self.new_label());
+                let dest = self.new_temporary();
                 let f = match op {
                     ast::BinOp::Add => Function::Add(l, r),
                     ast::BinOp::Sub => Function::Sub(l, r),
@@ -530,6 +1132,7 @@ impl Compiler {
                     ast::BinOp::Gt => Function::GreaterThan(l, r),
                     ast::BinOp::Le => Function::LessThanEqual(l, r),
                     ast::BinOp::Ge => Function::GreaterThanEqual(l, r),
+                    ast::BinOp::In => Function::In(l, r),
                     _ => self.error("unsupported binary op"),
                 };
                 self.output.body.push(Instruction::Classic {
@@ -543,16 +1146,25 @@ impl Compiler {
                     ast::UnaryOp::Pos => self.compile_expr(*expr),
                     ast::UnaryOp::Neg => {
                         let e = self.compile_expr(*expr);
-                        let dest = format!("__t{}", self.new_label());
+                        let dest = self.new_temporary();
                         self.output.body.push(Instruction::Classic {
                             dest: dest.clone(),
                             function: Function::Sub(Operand::Const(Const::Number(0.0)), e),
                         });
                         Operand::Var(dest)
                     }
+                    ast::UnaryOp::Typeof => {
+                        let e = self.compile_expr(*expr);
+                        let dest = self.new_temporary();
+                        self.output.body.push(Instruction::Classic {
+                            dest: dest.clone(),
+                            function: Function::Typeof(e),
+                        });
+                        Operand::Var(dest)
+                    }
                     ast::UnaryOp::Not => {
                         let e = self.compile_expr(*expr);
-                        let dest = format!("__t{}", self.new_label());
+                        let dest = self.new_temporary();
                         self.output.body.push(Instruction::Classic {
                             dest: dest.clone(),
                             function: Function::Inv(e),
@@ -560,26 +1172,29 @@ impl Compiler {
                         Operand::Var(dest)
                     }
                     ast::UnaryOp::Delete => {
-                        // delete obj.a -> set obj_a = undefined, return true
-                        if let ast::Expr::Member { object, property } = expr.as_ref() {
-                            if let ast::Expr::Identifier(obj_name) = object.as_ref() {
-                                let dest = format!("{}_{}", obj_name, property);
-                                self.output.body.push(Instruction::Assign {
-                                    dest,
-                                    src: Operand::Const(Const::Undefined),
-                                });
-                                return Operand::Const(Const::Boolean(true));
+                        // the property is removed from the object, the result
+                        // tells if it could be (DontDelete)
+                        let dest = self.new_temporary();
+                        let function = match *expr {
+                            target @ (ast::Expr::Member { .. } | ast::Expr::Index { .. }) => {
+                                let (obj, key) = self.compile_property_ref(target);
+                                SoloFunction::Delete(dest.clone(), obj, key)
                             }
-                        }
-                        self.error("unsupported delete target")
+                            ast::Expr::Identifier(name) => {
+                                SoloFunction::DeleteVar(dest.clone(), name)
+                            }
+                            _ => self.error("unsupported delete target"),
+                        };
+                        self.output.body.push(Instruction::Call { function });
+                        Operand::Var(dest)
                     }
                     _ => self.error("unsupported unary op"),
                 }
             }
             ast::Expr::Assign { target, op, value } => {
-                let val = self.compile_expr(*value);
                 match *target {
                     ast::Expr::Identifier(name) => {
+                        let val = self.compile_expr(*value);
                         match op {
                             ast::AssignOp::Assign => {
                                 self.output.body.push(Instruction::Assign {
@@ -603,62 +1218,49 @@ impl Compiler {
                         };
                         Operand::Var(name)
                     }
-                    ast::Expr::Member { object, property } => {
-                        let dest = match *object {
-                            ast::Expr::Identifier(obj_name) => format!("{}_{}", obj_name, property),
-                            ast::Expr::Member {
-                                object: inner,
-                                property: inner_prop,
-                            } => {
-                                if let ast::Expr::Identifier(inner_name) = *inner {
-                                    format!("{}_{}_{}", inner_name, inner_prop, property)
-                                } else {
-                                    self.error("unsupported assign target")
-                                }
-                            }
-                            _ => self.error("unsupported assign target"),
-                        };
-                        self.output.body.push(Instruction::Assign {
-                            dest: dest.clone(),
-                            src: val,
-                        });
-                        Operand::Var(dest)
-                    }
-                    ast::Expr::Index { object, index } => {
-                        if let ast::Expr::Identifier(obj_name) = *object {
-                            if let ast::Expr::Identifier(idx_name) = *index {
-                                let dest = format!("{}_{}", obj_name, idx_name);
-                                self.output.body.push(Instruction::Assign {
+                    target @ (ast::Expr::Member { .. } | ast::Expr::Index { .. }) => {
+                        // the object and the key are evaluated before the value
+                        let (obj, key) = self.compile_property_ref(target);
+                        let val = self.compile_expr(*value);
+                        let result = match op {
+                            ast::AssignOp::Assign => val,
+                            ast::AssignOp::AddAssign | ast::AssignOp::SubAssign => {
+                                let old = self.new_temporary();
+                                self.output.body.push(Instruction::Classic {
+                                    dest: old.clone(),
+                                    function: Function::GetProp(obj.clone(), key.clone()),
+                                });
+                                let dest = self.new_temporary();
+                                let old = Operand::Var(old);
+                                let function = match op {
+                                    ast::AssignOp::AddAssign => Function::Add(old, val),
+                                    _ => Function::Sub(old, val),
+                                };
+                                self.output.body.push(Instruction::Classic {
                                     dest: dest.clone(),
-                                    src: val,
+                                    function,
                                 });
                                 Operand::Var(dest)
-                            } else {
-                                self.error("unsupported index target")
                             }
-                        } else {
-                            self.error("unsupported index target")
-                        }
+                            _ => self.error("unsupported assign op"),
+                        };
+                        self.output.body.push(Instruction::Call {
+                            function: SoloFunction::SetProp(obj, key, result.clone()),
+                        });
+                        result
                     }
                     _ => self.error("unsupported assign target"),
                 }
             }
-            ast::Expr::Member { object, property } => match *object {
-                ast::Expr::Identifier(obj_name) => {
-                    Operand::Var(format!("{}_{}", obj_name, property))
-                }
-                ast::Expr::Member {
-                    object: inner,
-                    property: inner_prop,
-                } => {
-                    if let ast::Expr::Identifier(inner_name) = *inner {
-                        Operand::Var(format!("{}_{}_{}", inner_name, inner_prop, property))
-                    } else {
-                        self.error("unsupported nested member")
-                    }
-                }
-                _ => self.error("unsupported member"),
-            },
+            target @ (ast::Expr::Member { .. } | ast::Expr::Index { .. }) => {
+                let (obj, key) = self.compile_property_ref(target);
+                let dest = self.new_temporary();
+                self.output.body.push(Instruction::Classic {
+                    dest: dest.clone(),
+                    function: Function::GetProp(obj, key),
+                });
+                Operand::Var(dest)
+            }
             ast::Expr::Call { callee, args } => {
                 match *callee {
                     ast::Expr::Identifier(name) => {
@@ -678,15 +1280,19 @@ impl Compiler {
                     }
                     _ => self.error("unsupported callee"),
                 };
-                Operand::Const(Const::Undefined)
+                let dest = self.new_temporary();
+                self.output.body.push(Instruction::Call {
+                    function: SoloFunction::CallResult(dest.clone()),
+                });
+                Operand::Var(dest)
             }
             ast::Expr::Ternary { cond, then_, else_ } => {
-                let dest = format!("__t{}", self.new_label());
+                let dest = self.new_temporary();
                 let else_label = self.new_label();
                 let end_label = self.new_label();
 
                 let c = self.compile_expr(*cond);
-                let not_c = format!("__t{}", self.new_label());
+                let not_c = self.new_temporary();
                 self.output.body.push(Instruction::Classic {
                     dest: not_c.clone(),
                     function: Function::Inv(c),
@@ -743,7 +1349,7 @@ impl Compiler {
                     } else {
                         // Post-incrément: i++
                         // Sauvegarder la valeur d'origine
-                        let old_val = format!("__t{}", self.new_label());
+                        let old_val = self.new_temporary();
                         self.output.body.push(Instruction::Classic {
                             dest: old_val.clone(),
                             function: Function::Noop(arg.clone()),
@@ -774,6 +1380,22 @@ impl Compiler {
         }
     }
 
+    // Object and key operands of `obj.name` or `obj[expr]`, in this order
+    fn compile_property_ref(&mut self, expr: ast::Expr) -> (Operand, Operand) {
+        match expr {
+            ast::Expr::Member { object, property } => {
+                let obj = self.compile_expr(*object);
+                (obj, Operand::Const(Const::String(property)))
+            }
+            ast::Expr::Index { object, index } => {
+                let obj = self.compile_expr(*object);
+                let key = self.compile_expr(*index);
+                (obj, key)
+            }
+            _ => self.error("expected a property reference"),
+        }
+    }
+
     fn compile_for_init(&mut self, forinit: ast::ForInit) -> Operand {
         match forinit {
             ast::ForInit::Var(vars) => {
@@ -0,0 +1,453 @@
+/*
+ * Control-flow graph of the IR
+ *
+ * The IR is a flat list of instructions, this module cuts it into basic blocks:
+ *  - a block starts at a Label (or at the entry of the function)
+ *  - a block ends after a Jump, a JumpIf, a Return or a Throw
+ *  - a TryStart ends a block too: the protected code may jump to its handler, so
+ *    the handler is a successor along with the next block
+ *  - every block running an instruction inside a try has an edge to the innermost
+ *    handler, and a definition inside a try ends its block: the handler sees the
+ *    variables as they were at the end of one of its predecessors
+ *
+ * A CFG is built for one "function" at a time: the top-level code or the body of
+ * a FnStart/FnEnd pair. Nested function declarations are kept as a single opaque
+ * instruction (the index of their FnStart) in the block where they appear.
+ *
+ * Blocks refer to the instructions by index in `Program.body`, so the program
+ * must not be modified while a CFG built from it is in use.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use super::{Instruction, Program, SoloFunction};
+
+#[derive(Debug, Default)]
+pub struct BasicBlock {
+    pub label: Option<i64>,       // label starting the block, if any
+    pub instructions: Vec<usize>, // indices in Program.body
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct Cfg {
+    pub name: Option<String>,          // None for the top-level code
+    pub blocks: Vec<BasicBlock>,       // blocks[0] is the entry block
+    pub nested: HashMap<usize, usize>, // FnStart index -> FnEnd index of nested functions
+}
+
+// Index of the FnEnd closing the FnStart at `start`
+pub fn function_end(body: &[Instruction], start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, instr) in body.iter().enumerate().skip(start) {
+        match instr {
+            Instruction::Call {
+                function: SoloFunction::FnStart(..),
+            } => depth += 1,
+            Instruction::Call {
+                function: SoloFunction::FnEnd(),
+            } => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+impl Cfg {
+    // CFG of the top-level code
+    pub fn build(program: &Program) -> Cfg {
+        Self::from_range(program, 0, program.body.len(), None)
+    }
+
+    // CFG of the body of the first function declared with this name
+    pub fn for_function(program: &Program, name: &str) -> Option<Cfg> {
+        let start = program.body.iter().position(|instr| {
+            matches!(
+                instr,
+                Instruction::Call {
+                    function: SoloFunction::FnStart(n, _)
+                } if n == name
+            )
+        })?;
+        let end = function_end(&program.body, start)?;
+        Some(Self::from_range(
+            program,
+            start + 1,
+            end,
+            Some(name.to_string()),
+        ))
+    }
+
+    // Build the CFG of body[start..end]
+    pub fn from_range(program: &Program, start: usize, end: usize, name: Option<String>) -> Cfg {
+        let body = &program.body;
+        let cfg = Self::cut(body, start, end, name.clone(), &HashSet::new());
+        let handlers = cfg.handler_labels(body);
+
+        // the blocks are cut again so the protected definitions end them
+        let split_after: HashSet<usize> = cfg
+            .blocks
+            .iter()
+            .flat_map(|b| &b.instructions[..b.instructions.len().saturating_sub(1)])
+            .copied()
+            .filter(|i| handlers.contains_key(i) && body[*i].dest().is_some())
+            .collect();
+        let mut cfg = if split_after.is_empty() {
+            cfg
+        } else {
+            Self::cut(body, start, end, name, &split_after)
+        };
+
+        for b in 0..cfg.blocks.len() {
+            let targets: Vec<usize> = cfg.blocks[b]
+                .instructions
+                .iter()
+                .filter_map(|i| handlers.get(i))
+                .filter_map(|&label| cfg.block_of_label(label))
+                .collect();
+            for h in targets {
+                cfg.add_edge(b, h);
+            }
+        }
+        cfg
+    }
+
+    // Cut body[start..end] into blocks, a block also ends after the
+    // instructions of `split_after`
+    fn cut(
+        body: &[Instruction],
+        start: usize,
+        end: usize,
+        name: Option<String>,
+        split_after: &HashSet<usize>,
+    ) -> Cfg {
+        let mut cfg = Cfg {
+            name,
+            blocks: vec![BasicBlock::default()],
+            nested: HashMap::new(),
+        };
+
+        let mut i = start;
+        while i < end {
+            match &body[i] {
+                Instruction::Call {
+                    function: SoloFunction::Label(id),
+                } => {
+                    let current = cfg.blocks.last().unwrap();
+                    if !current.instructions.is_empty() || current.label.is_some() {
+                        cfg.blocks.push(BasicBlock::default());
+                    }
+                    let current = cfg.blocks.last_mut().unwrap();
+                    current.label = Some(*id);
+                    current.instructions.push(i);
+                }
+                Instruction::Call {
+                    function: SoloFunction::FnStart(..),
+                } => {
+                    let fn_end = function_end(body, i).unwrap_or(end - 1).min(end - 1);
+                    cfg.nested.insert(i, fn_end);
+                    cfg.blocks.last_mut().unwrap().instructions.push(i);
+                    i = fn_end;
+                }
+                Instruction::Call {
+                    function:
+                        SoloFunction::Jump(_)
+                        | SoloFunction::JumpIf(..)
+                        | SoloFunction::Return(_)
+                        | SoloFunction::TryStart(_)
+                        | SoloFunction::Throw(_),
+                } => {
+                    cfg.blocks.last_mut().unwrap().instructions.push(i);
+                    if i + 1 < end {
+                        cfg.blocks.push(BasicBlock::default());
+                    }
+                }
+                _ => {
+                    cfg.blocks.last_mut().unwrap().instructions.push(i);
+                    if split_after.contains(&i) && i + 1 < end {
+                        cfg.blocks.push(BasicBlock::default());
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        cfg.link(body);
+        cfg
+    }
+
+    fn link(&mut self, body: &[Instruction]) {
+        let labels: HashMap<i64, usize> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(b, block)| block.label.map(|l| (l, b)))
+            .collect();
+
+        for b in 0..self.blocks.len() {
+            let next = (b + 1 < self.blocks.len()).then_some(b + 1);
+            let last = self.blocks[b].instructions.last().map(|&i| &body[i]);
+
+            let successors: Vec<usize> = match last {
+                Some(Instruction::Call {
+                    function: SoloFunction::Jump(l),
+                }) => labels.get(l).copied().into_iter().collect(),
+                Some(Instruction::Call {
+                    function: SoloFunction::JumpIf(_, l) | SoloFunction::TryStart(l),
+                }) => labels.get(l).copied().into_iter().chain(next).collect(),
+                Some(Instruction::Call {
+                    function: SoloFunction::Return(_) | SoloFunction::Throw(_),
+                }) => vec![],
+                _ => next.into_iter().collect(),
+            };
+
+            for s in successors {
+                self.add_edge(b, s);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) {
+        if !self.blocks[from].successors.contains(&to) {
+            self.blocks[from].successors.push(to);
+            self.blocks[to].predecessors.push(from);
+        }
+    }
+
+    // Innermost handler of every instruction run inside a try, found by
+    // walking the blocks with the stack of installed handlers. The compiler
+    // reaches a block with the same stack from all its predecessors (a break
+    // out of a try removes the handler before its jump).
+    fn handler_labels(&self, body: &[Instruction]) -> HashMap<usize, i64> {
+        let mut handlers = HashMap::new();
+        let mut stacks: Vec<Option<Vec<i64>>> = vec![None; self.blocks.len()];
+        stacks[0] = Some(Vec::new());
+        let mut work = vec![0usize];
+
+        while let Some(b) = work.pop() {
+            let mut stack = stacks[b].clone().unwrap_or_default();
+            for &i in &self.blocks[b].instructions {
+                match &body[i] {
+                    Instruction::Call {
+                        function: SoloFunction::TryEnd(),
+                    } => {
+                        stack.pop();
+                        continue;
+                    }
+                    Instruction::Call {
+                        function: SoloFunction::TryStart(label),
+                    } => {
+                        // the handler runs with the handlers around the try
+                        if let Some(h) = self.block_of_label(*label)
+                            && stacks[h].is_none()
+                        {
+                            stacks[h] = Some(stack.clone());
+                            work.push(h);
+                        }
+                        if let Some(&outer) = stack.last() {
+                            handlers.insert(i, outer);
+                        }
+                        stack.push(*label);
+                        continue;
+                    }
+                    _ => {}
+                }
+                if let Some(&label) = stack.last() {
+                    handlers.insert(i, label);
+                }
+            }
+            for &s in &self.blocks[b].successors {
+                if stacks[s].is_none() {
+                    stacks[s] = Some(stack.clone());
+                    work.push(s);
+                }
+            }
+        }
+        handlers
+    }
+
+    pub fn block_of_label(&self, label: i64) -> Option<usize> {
+        self.blocks.iter().position(|b| b.label == Some(label))
+    }
+
+    // Blocks reachable from the entry, in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::with_capacity(self.blocks.len());
+        // iterative DFS: (block, index of the next successor to visit)
+        let mut stack = vec![(0usize, 0usize)];
+        visited[0] = true;
+
+        while let Some((b, next)) = stack.pop() {
+            if let Some(&s) = self.blocks[b].successors.get(next) {
+                stack.push((b, next + 1));
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                order.push(b);
+            }
+        }
+
+        order.reverse();
+        order
+    }
+
+    // "A Simple, Fast Dominance Algorithm" (Cooper, Harvey, Kennedy)
+    pub fn dominators(&self) -> Dominators {
+        let rpo = self.reverse_postorder();
+        let mut rpo_index = vec![usize::MAX; self.blocks.len()];
+        for (i, &b) in rpo.iter().enumerate() {
+            rpo_index[b] = i;
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo.iter().skip(1) {
+                let mut new_idom: Option<usize> = None;
+                for &p in &self.blocks[b].predecessors {
+                    if idom[p].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(current) => intersect(&idom, &rpo_index, p, current),
+                    });
+                }
+                if new_idom.is_some() && idom[b] != new_idom {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        Dominators { idom }
+    }
+
+    // Graphviz output, one node per basic block
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut out = String::new();
+        let name = self.name.as_deref().unwrap_or("<main>");
+        writeln!(out, "digraph \"{}\" {{", escape_dot(name)).unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+
+        for (b, block) in self.blocks.iter().enumerate() {
+            let mut text = format!("B{}\\l", b);
+            for &i in &block.instructions {
+                let line = match self.nested.get(&i) {
+                    Some(end) => format!("{} ... ({} instructions)", program.body[i], end - i - 1),
+                    None => program.body[i].to_string(),
+                };
+                text.push_str(&escape_dot(&line));
+                text.push_str("\\l");
+            }
+            writeln!(out, "    B{} [label=\"{}\"];", b, text).unwrap();
+        }
+
+        for (b, block) in self.blocks.iter().enumerate() {
+            for s in &block.successors {
+                writeln!(out, "    B{} -> B{};", b, s).unwrap();
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn intersect(idom: &[Option<usize>], rpo_index: &[usize], a: usize, b: usize) -> usize {
+    let (mut a, mut b) = (a, b);
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Dominator tree of a CFG (unreachable blocks are dominated by nothing)
+pub struct Dominators {
+    idom: Vec<Option<usize>>,
+}
+
+impl Dominators {
+    // Immediate dominator of a block (None for the entry and unreachable blocks)
+    pub fn immediate(&self, block: usize) -> Option<usize> {
+        if block == 0 {
+            return None;
+        }
+        self.idom[block]
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.idom[block].is_some()
+    }
+
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        let mut current = b;
+        loop {
+            if current == a {
+                return true;
+            }
+            match self.immediate(current) {
+                Some(up) => current = up,
+                None => return false,
+            }
+        }
+    }
+
+    // Blocks immediately dominated by `block`
+    pub fn children(&self, block: usize) -> Vec<usize> {
+        (0..self.idom.len())
+            .filter(|&b| self.immediate(b) == Some(block))
+            .collect()
+    }
+
+    // Dominance frontier of every block
+    pub fn frontiers(&self, cfg: &Cfg) -> Vec<Vec<usize>> {
+        let mut df: Vec<Vec<usize>> = vec![Vec::new(); cfg.blocks.len()];
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            if block.predecessors.len() < 2 || !self.is_reachable(b) {
+                continue;
+            }
+            for &p in &block.predecessors {
+                if !self.is_reachable(p) {
+                    continue;
+                }
+                let mut runner = p;
+                while Some(runner) != self.immediate(b) {
+                    if !df[runner].contains(&b) {
+                        df[runner].push(b);
+                    }
+                    match self.immediate(runner) {
+                        Some(up) => runner = up,
+                        None => break,
+                    }
+                }
+            }
+        }
+        df
+    }
+}
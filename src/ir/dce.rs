@@ -0,0 +1,153 @@
+/*
+ * Dead code elimination on the IR
+ *
+ * The AST->IR compiler is naive and leaves a lot of garbage behind:
+ *  - code after a Jump, a Return or a Throw (ex: the `__ret_` assignment emitted after `Return`)
+ *  - labels nobody jumps to (ex: the `cond_label` of an `if`)
+ *  - `__tN` temporaries that are computed but never read
+ *
+ * The three cleanups feed each other (removing a dead jump can make a label unused,
+ * which makes the code behind it unreachable...) so we run them until nothing changes.
+ */
+
+use std::collections::HashSet;
+
+use super::{Function, Instruction, Operand, Program, SoloFunction, is_temporary};
+
+// Remove unreachable instructions, unused labels and dead temporaries.
+// Returns the number of removed instructions.
+pub fn eliminate_dead_code(program: &mut Program) -> usize {
+    let before = program.body.len();
+
+    loop {
+        let removed = remove_unreachable(&mut program.body)
+            + remove_unused_labels(&mut program.body)
+            + remove_dead_temporaries(&mut program.body);
+        if removed == 0 {
+            break;
+        }
+    }
+
+    before - program.body.len()
+}
+
+fn referenced_labels(body: &[Instruction]) -> HashSet<i64> {
+    body.iter().filter_map(|i| i.jump_target()).collect()
+}
+
+// Everything between an unconditional transfer (Jump, Return, Throw) and the next
+// referenced label is dead. Function declarations are always kept: their body
+// has its own entry point and they are skipped over by the outer code.
+fn remove_unreachable(body: &mut Vec<Instruction>) -> usize {
+    let targets = referenced_labels(body);
+    let mut reachable = true;
+    let mut outer: Vec<bool> = Vec::new();
+    let mut keep = Vec::with_capacity(body.len());
+
+    for instr in body.iter() {
+        let kept = match instr {
+            Instruction::Call {
+                function: SoloFunction::FnStart(..),
+            } => {
+                outer.push(reachable);
+                reachable = true;
+                true
+            }
+            Instruction::Call {
+                function: SoloFunction::FnEnd(),
+            } => {
+                reachable = outer.pop().unwrap_or(true);
+                true
+            }
+            Instruction::Call {
+                function: SoloFunction::Label(id),
+            } => {
+                if targets.contains(id) {
+                    reachable = true;
+                }
+                reachable
+            }
+            Instruction::Call {
+                function: SoloFunction::Jump(_) | SoloFunction::Return(_) | SoloFunction::Throw(_),
+            } => {
+                let kept = reachable;
+                reachable = false;
+                kept
+            }
+            _ => reachable,
+        };
+        keep.push(kept);
+    }
+
+    retain_indices(body, &keep)
+}
+
+fn remove_unused_labels(body: &mut Vec<Instruction>) -> usize {
+    let targets = referenced_labels(body);
+    let keep: Vec<bool> = body
+        .iter()
+        .map(|instr| match instr {
+            Instruction::Call {
+                function: SoloFunction::Label(id),
+            } => targets.contains(id),
+            _ => true,
+        })
+        .collect();
+
+    retain_indices(body, &keep)
+}
+
+// Object/array construction and the IR functions that don't convert their
+// operands have no side effects, so an assignment to a temporary that is never
+// read can go away. The other functions can call the valueOf or toString of an
+// object operand. Reading a named variable can raise a ReferenceError, so only
+// constants and temporaries may be operands of a removed instruction.
+fn remove_dead_temporaries(body: &mut Vec<Instruction>) -> usize {
+    let read: HashSet<&str> = body
+        .iter()
+        .flat_map(|i| i.uses())
+        .filter_map(|op| match op {
+            Operand::Var(name) => Some(name.as_str()),
+            Operand::Const(_) => None,
+        })
+        .collect();
+
+    let keep: Vec<bool> = body
+        .iter()
+        .map(|instr| {
+            let removable = matches!(
+                instr,
+                Instruction::Assign { .. }
+                    | Instruction::Classic {
+                        function: Function::Noop(_)
+                            | Function::Inv(_)
+                            | Function::StrictEqual(..)
+                            | Function::Typeof(_),
+                        ..
+                    }
+                    | Instruction::Call {
+                        function: SoloFunction::MakeObject(..) | SoloFunction::MakeArray(..),
+                    }
+            );
+            let pure_operands = instr.uses().iter().all(|op| match op {
+                Operand::Var(name) => is_temporary(name),
+                Operand::Const(_) => true,
+            });
+            match instr.dest() {
+                Some(dest) if removable && pure_operands && is_temporary(dest) => {
+                    read.contains(dest)
+                }
+                _ => true,
+            }
+        })
+        .collect();
+
+    retain_indices(body, &keep)
+}
+
+fn retain_indices(body: &mut Vec<Instruction>, keep: &[bool]) -> usize {
+    let before = body.len();
+    let mut flags = keep.iter();
+    body.retain(|_| *flags.next().unwrap_or(&true));
+    before - body.len()
+}
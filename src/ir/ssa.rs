@@ -0,0 +1,598 @@
+/*
+ * SSA form of the IR
+ *
+ * `construct` turns the CFG of one function into SSA (Cytron et al.): each
+ * definition of a variable creates a new version `name#N` and phi nodes merge the
+ * versions at join points. A read that no definition reaches keeps the original
+ * name (version 0, the value the variable had when the function was entered).
+ *
+ * `SsaFunction::destruct` goes back to plain IR:
+ *  - phis become copies at the end of the predecessors (critical edges are split)
+ *  - the edges to an exception handler get no copies, the exception can come from
+ *    any point of the block: the phis of a handler are only undone by renaming
+ *    their versions back to the original variable
+ *  - versions of a variable that never interfere are renamed back to the original
+ *    name, variables are resolved by name at runtime and a callee may read them
+ *  - versions that do interfere (after a pass moved things around) keep their own
+ *    name and the original variable is updated after every definition
+ *
+ * Calls are not treated as definitions: with dynamic scoping a callee could write
+ * any variable, so passes working on SSA must not move reads across calls.
+ *
+ * Nested function declarations are copied as-is, `round_trip` handles them recursively.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use super::cfg::{self, Cfg};
+use super::{Instruction, Operand, Program, SoloFunction};
+
+#[derive(Debug, Clone)]
+pub struct Phi {
+    pub dest: String,
+    pub args: Vec<(usize, Operand)>, // (predecessor block, incoming value)
+}
+
+#[derive(Debug, Default)]
+pub struct SsaBlock {
+    pub label: Option<i64>,
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct SsaFunction {
+    pub name: Option<String>,
+    pub blocks: Vec<SsaBlock>,
+}
+
+// "x#3" -> "x"
+pub fn base_name(name: &str) -> &str {
+    name.split('#').next().unwrap_or(name)
+}
+
+// Convert every function of the program (and the top-level code) to SSA and back
+pub fn round_trip(program: &Program) -> Program {
+    let mut next_label = max_label(&program.body) + 1;
+    Program {
+        body: round_trip_body(&program.body, &mut next_label),
+    }
+}
+
+fn round_trip_body(body: &[Instruction], next_label: &mut i64) -> Vec<Instruction> {
+    let program = Program {
+        body: body.to_vec(),
+    };
+    let cfg = Cfg::build(&program);
+    let flat = construct(&program, &cfg).destruct(next_label);
+
+    let mut out = Vec::with_capacity(flat.len());
+    let mut i = 0;
+    while i < flat.len() {
+        if let Instruction::Call {
+            function: SoloFunction::FnStart(..),
+        } = &flat[i]
+            && let Some(end) = cfg::function_end(&flat, i)
+        {
+            out.push(flat[i].clone());
+            out.extend(round_trip_body(&flat[i + 1..end], next_label));
+            out.push(flat[end].clone());
+            i = end + 1;
+            continue;
+        }
+        out.push(flat[i].clone());
+        i += 1;
+    }
+    out
+}
+
+fn max_label(body: &[Instruction]) -> i64 {
+    body.iter()
+        .filter_map(|instr| match instr {
+            Instruction::Call {
+                function: SoloFunction::Label(id),
+            } => Some(*id),
+            _ => instr.jump_target(),
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Apply `f` on every instruction, skipping the bodies of nested functions
+fn for_each_top_level<'a>(
+    instructions: impl Iterator<Item = &'a mut Instruction>,
+    mut f: impl FnMut(&mut Instruction),
+) {
+    let mut depth = 0usize;
+    for instr in instructions {
+        match instr {
+            Instruction::Call {
+                function: SoloFunction::FnStart(..),
+            } => depth += 1,
+            Instruction::Call {
+                function: SoloFunction::FnEnd(),
+            } => depth = depth.saturating_sub(1),
+            _ if depth == 0 => f(instr),
+            _ => {}
+        }
+    }
+}
+
+fn rename_uses(instr: &mut Instruction, mut rename: impl FnMut(&str) -> String) {
+    for op in instr.uses_mut() {
+        if let Operand::Var(name) = op {
+            *name = rename(name);
+        }
+    }
+    if let Instruction::Call {
+        function: SoloFunction::Kill(Operand::Var(name)),
+    } = instr
+    {
+        *name = rename(name);
+    }
+}
+
+pub fn construct(program: &Program, cfg: &Cfg) -> SsaFunction {
+    let body = &program.body;
+    let mut blocks: Vec<SsaBlock> = cfg
+        .blocks
+        .iter()
+        .map(|b| {
+            let mut instructions = Vec::new();
+            for &i in &b.instructions {
+                match cfg.nested.get(&i) {
+                    Some(&end) => instructions.extend(body[i..=end].iter().cloned()),
+                    None => instructions.push(body[i].clone()),
+                }
+            }
+            SsaBlock {
+                label: b.label,
+                phis: Vec::new(),
+                instructions,
+                successors: b.successors.clone(),
+                predecessors: b.predecessors.clone(),
+            }
+        })
+        .collect();
+
+    // blocks defining each variable
+    let mut defsites: HashMap<String, HashSet<usize>> = HashMap::new();
+    for (b, block) in blocks.iter_mut().enumerate() {
+        for_each_top_level(block.instructions.iter_mut(), |instr| {
+            if let Some(dest) = instr.dest() {
+                defsites.entry(dest.to_string()).or_default().insert(b);
+            }
+        });
+    }
+
+    // phi placement on the iterated dominance frontier
+    let doms = cfg.dominators();
+    let frontiers = doms.frontiers(cfg);
+    let mut vars: Vec<&String> = defsites.keys().collect();
+    vars.sort();
+    for var in vars {
+        let mut has_phi: HashSet<usize> = HashSet::new();
+        let mut work: Vec<usize> = defsites[var].iter().copied().collect();
+        while let Some(b) = work.pop() {
+            for &f in &frontiers[b] {
+                if !has_phi.insert(f) {
+                    continue;
+                }
+                let args = blocks[f]
+                    .predecessors
+                    .iter()
+                    .map(|&p| (p, Operand::Var(var.clone())))
+                    .collect();
+                blocks[f].phis.push(Phi {
+                    dest: var.clone(),
+                    args,
+                });
+                if !defsites[var].contains(&f) {
+                    work.push(f);
+                }
+            }
+        }
+    }
+
+    let mut renamer = Renamer {
+        stacks: HashMap::new(),
+        counters: HashMap::new(),
+    };
+    let children: Vec<Vec<usize>> = (0..blocks.len()).map(|b| doms.children(b)).collect();
+    renamer.rename_block(&mut blocks, &children, 0);
+
+    SsaFunction {
+        name: cfg.name.clone(),
+        blocks,
+    }
+}
+
+struct Renamer {
+    stacks: HashMap<String, Vec<String>>,
+    counters: HashMap<String, usize>,
+}
+
+impl Renamer {
+    fn current(&self, name: &str) -> String {
+        self.stacks
+            .get(name)
+            .and_then(|s| s.last())
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn new_version(&mut self, base: &str) -> String {
+        let counter = self.counters.entry(base.to_string()).or_insert(0);
+        *counter += 1;
+        let version = format!("{}#{}", base, counter);
+        self.stacks
+            .entry(base.to_string())
+            .or_default()
+            .push(version.clone());
+        version
+    }
+
+    fn rename_block(&mut self, blocks: &mut [SsaBlock], children: &[Vec<usize>], b: usize) {
+        let mut pushed: Vec<String> = Vec::new();
+
+        for i in 0..blocks[b].phis.len() {
+            let base = blocks[b].phis[i].dest.clone();
+            blocks[b].phis[i].dest = self.new_version(&base);
+            pushed.push(base);
+        }
+
+        let mut instructions = std::mem::take(&mut blocks[b].instructions);
+        for_each_top_level(instructions.iter_mut(), |instr| {
+            rename_uses(instr, |name| self.current(name));
+            if let Some(dest) = instr.dest_mut() {
+                let base = dest.clone();
+                *dest = self.new_version(&base);
+                pushed.push(base);
+            }
+        });
+        blocks[b].instructions = instructions;
+
+        for s in blocks[b].successors.clone() {
+            for phi in blocks[s].phis.iter_mut() {
+                let value = Operand::Var(self.current(base_name(&phi.dest)));
+                for (p, arg) in phi.args.iter_mut() {
+                    if *p == b {
+                        *arg = value.clone();
+                    }
+                }
+            }
+        }
+
+        for &child in &children[b] {
+            self.rename_block(blocks, children, child);
+        }
+
+        for base in pushed {
+            if let Some(stack) = self.stacks.get_mut(&base) {
+                stack.pop();
+            }
+        }
+    }
+}
+
+// Copies implementing the phis of a block for the edge coming from `pred`.
+// Phis read their arguments in parallel, go through temporaries when a copy
+// would overwrite a value still needed by another one.
+fn edge_copies(phis: &[Phi], pred: usize) -> Vec<Instruction> {
+    let moves: Vec<(&String, &Operand)> = phis
+        .iter()
+        .filter_map(|phi| {
+            phi.args
+                .iter()
+                .find(|(p, _)| *p == pred)
+                .map(|(_, arg)| (&phi.dest, arg))
+        })
+        .collect();
+
+    let dests: HashSet<&str> = moves.iter().map(|(d, _)| d.as_str()).collect();
+    let conflict = moves
+        .iter()
+        .any(|(_, src)| matches!(src, Operand::Var(v) if dests.contains(v.as_str())));
+
+    if !conflict {
+        return moves
+            .into_iter()
+            .map(|(dest, src)| Instruction::Assign {
+                dest: dest.clone(),
+                src: src.clone(),
+            })
+            .collect();
+    }
+
+    let mut copies: Vec<Instruction> = moves
+        .iter()
+        .map(|(dest, src)| Instruction::Assign {
+            dest: format!("{}'", dest),
+            src: (*src).clone(),
+        })
+        .collect();
+    copies.extend(moves.iter().map(|(dest, _)| Instruction::Assign {
+        dest: dest.to_string(),
+        src: Operand::Var(format!("{}'", dest)),
+    }));
+    copies
+}
+
+fn is_terminator(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Call {
+            function: SoloFunction::Jump(_) | SoloFunction::JumpIf(..) | SoloFunction::Return(_),
+        }
+    )
+}
+
+// First instructions of a catch, only reached by exceptions
+fn starts_handler<'a>(instructions: impl IntoIterator<Item = &'a Instruction>) -> bool {
+    instructions
+        .into_iter()
+        .find(|i| {
+            !matches!(
+                i,
+                Instruction::Call {
+                    function: SoloFunction::Label(_)
+                }
+            )
+        })
+        .is_some_and(|i| {
+            matches!(
+                i,
+                Instruction::Call {
+                    function: SoloFunction::Catch(_)
+                }
+            )
+        })
+}
+
+impl SsaFunction {
+    // Back to plain IR, `next_label` is used to create the labels of split edges
+    pub fn destruct(self, next_label: &mut i64) -> Vec<Instruction> {
+        let blocks = self.blocks;
+        // (instruction, is a copy inserted by the out-of-SSA translation)
+        let mut code: Vec<(Instruction, bool)> = Vec::new();
+        let mut split_edges: Vec<(Instruction, bool)> = Vec::new();
+
+        for (b, block) in blocks.iter().enumerate() {
+            let mut instructions: Vec<(Instruction, bool)> = block
+                .instructions
+                .iter()
+                .map(|i| (i.clone(), false))
+                .collect();
+            let mut fallthrough: Vec<Instruction> = Vec::new();
+
+            let successors: Vec<usize> = block
+                .successors
+                .iter()
+                .copied()
+                .filter(|&s| !starts_handler(&blocks[s].instructions))
+                .collect();
+            for &s in &successors {
+                if blocks[s].phis.is_empty() {
+                    continue;
+                }
+                let copies = edge_copies(&blocks[s].phis, b);
+
+                if successors.len() == 1 {
+                    let at = match instructions.last() {
+                        Some((last, false)) if is_terminator(last) => instructions.len() - 1,
+                        _ => instructions.len(),
+                    };
+                    instructions.splice(at..at, copies.into_iter().map(|c| (c, true)));
+                    continue;
+                }
+
+                // critical edge: the block ends with a JumpIf
+                let Some((
+                    Instruction::Call {
+                        function: SoloFunction::JumpIf(_, target),
+                    },
+                    _,
+                )) = instructions.last_mut()
+                else {
+                    continue;
+                };
+                if blocks[s].label == Some(*target) {
+                    let label = *next_label;
+                    *next_label += 1;
+                    let original = *target;
+                    *target = label;
+                    split_edges.push((
+                        Instruction::Call {
+                            function: SoloFunction::Label(label),
+                        },
+                        true,
+                    ));
+                    split_edges.extend(copies.into_iter().map(|c| (c, true)));
+                    split_edges.push((
+                        Instruction::Call {
+                            function: SoloFunction::Jump(original),
+                        },
+                        true,
+                    ));
+                } else {
+                    fallthrough = copies;
+                }
+            }
+
+            code.extend(instructions);
+            code.extend(fallthrough.into_iter().map(|c| (c, true)));
+        }
+
+        if !split_edges.is_empty() {
+            let exit = *next_label;
+            *next_label += 1;
+            code.push((
+                Instruction::Call {
+                    function: SoloFunction::Jump(exit),
+                },
+                true,
+            ));
+            code.extend(split_edges);
+            code.push((
+                Instruction::Call {
+                    function: SoloFunction::Label(exit),
+                },
+                true,
+            ));
+        }
+
+        coalesce(code)
+    }
+}
+
+// Rename the versions back to their original variable when they don't interfere
+fn coalesce(code: Vec<(Instruction, bool)>) -> Vec<Instruction> {
+    let interfering = interfering_variables(&code);
+
+    let mut out = Vec::with_capacity(code.len());
+    let mut entry_copies: HashSet<String> = HashSet::new();
+    let mut depth = 0usize;
+
+    for (mut instr, is_copy) in code {
+        match instr {
+            Instruction::Call {
+                function: SoloFunction::FnStart(..),
+            } => depth += 1,
+            Instruction::Call {
+                function: SoloFunction::FnEnd(),
+            } => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if depth > 0 {
+            out.push(instr);
+            continue;
+        }
+
+        let rename = |name: &str| -> String {
+            let base = base_name(name);
+            if !interfering.contains(base) {
+                base.to_string()
+            } else if name == base {
+                format!("{}#0", base)
+            } else {
+                name.to_string()
+            }
+        };
+
+        rename_uses(&mut instr, |name| {
+            if interfering.contains(name) {
+                entry_copies.insert(name.to_string());
+            }
+            rename(name)
+        });
+        let mut copy_back = None;
+        if let Some(dest) = instr.dest_mut() {
+            let base = base_name(dest).to_string();
+            if interfering.contains(&base) && !is_copy {
+                copy_back = Some(Instruction::Assign {
+                    dest: base,
+                    src: Operand::Var(dest.clone()),
+                });
+            } else {
+                *dest = rename(dest);
+            }
+        }
+
+        if let Instruction::Assign {
+            dest,
+            src: Operand::Var(src),
+        } = &instr
+            && dest == src
+        {
+            continue;
+        }
+
+        out.push(instr);
+        out.extend(copy_back);
+    }
+
+    let mut entry_copies: Vec<String> = entry_copies.into_iter().collect();
+    entry_copies.sort();
+    let entry = entry_copies.into_iter().map(|base| Instruction::Assign {
+        dest: format!("{}#0", base),
+        src: Operand::Var(base),
+    });
+    entry.chain(out).collect()
+}
+
+// Variables having two versions alive at the same time
+fn interfering_variables(code: &[(Instruction, bool)]) -> HashSet<String> {
+    let program = Program {
+        body: code.iter().map(|(i, _)| i.clone()).collect(),
+    };
+    let cfg = Cfg::build(&program);
+
+    let step = |instr: &Instruction,
+                live: &mut HashSet<String>,
+                conflicts: &mut HashSet<String>| {
+        if let Some(dest) = instr.dest() {
+            let copied_from = match instr {
+                Instruction::Assign {
+                    src: Operand::Var(src),
+                    ..
+                } => Some(src.as_str()),
+                _ => None,
+            };
+            for v in live.iter() {
+                if v != dest && base_name(v) == base_name(dest) && Some(v.as_str()) != copied_from {
+                    conflicts.insert(base_name(dest).to_string());
+                }
+            }
+            live.remove(dest);
+        }
+        for op in instr.uses() {
+            if let Operand::Var(name) = op {
+                live.insert(name.clone());
+            }
+        }
+        if let Instruction::Call {
+            function: SoloFunction::Kill(Operand::Var(name)),
+        } = instr
+        {
+            live.insert(name.clone());
+        }
+    };
+
+    // the phis of a handler have no copies: their versions are never defined,
+    // they must not look alive in the protected code
+    let defined: HashSet<&str> = code.iter().filter_map(|(i, _)| i.dest()).collect();
+    let handlers: Vec<bool> = cfg
+        .blocks
+        .iter()
+        .map(|b| starts_handler(b.instructions.iter().map(|&i| &program.body[i])))
+        .collect();
+
+    let flows_into = |s: usize, v: &str| !handlers[s] || base_name(v) == v || defined.contains(v);
+
+    let mut live_in: Vec<HashSet<String>> = vec![HashSet::new(); cfg.blocks.len()];
+    let mut conflicts: HashSet<String> = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        conflicts.clear();
+        for b in (0..cfg.blocks.len()).rev() {
+            let mut live: HashSet<String> = cfg.blocks[b]
+                .successors
+                .iter()
+                .flat_map(|&s| live_in[s].iter().filter(move |v| flows_into(s, v)))
+                .cloned()
+                .collect();
+            for &i in cfg.blocks[b].instructions.iter().rev() {
+                step(&program.body[i], &mut live, &mut conflicts);
+            }
+            if live != live_in[b] {
+                live_in[b] = live;
+                changed = true;
+            }
+        }
+    }
+
+    conflicts
+}
@@ -0,0 +1,131 @@
+/*
+ * Well-formedness checks on the IR
+ *
+ * The bytecode compiler trusts its input and panics on malformed IR
+ * ("Undefined label", "FnEnd without matching FnStart"...). This pass looks for
+ * those problems beforehand and reports all of them:
+ *  - labels are defined exactly once
+ *  - jumps target a label defined in the same function
+ *  - FnStart/FnEnd are correctly nested
+ *  - Return only appears inside a function
+ *  - ForInNext reads an iterator created by a ForInStart earlier in the function
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::{Instruction, Operand, Program, SoloFunction};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub index: usize, // position of the faulty instruction in Program.body
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "instruction {}: {}", self.index, self.message)
+    }
+}
+
+pub fn validate(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut error = |index: usize, message: String| diagnostics.push(Diagnostic { index, message });
+
+    // enclosing function of every instruction (index of its FnStart)
+    let mut owner: Vec<Option<usize>> = Vec::with_capacity(program.body.len());
+    let mut functions: Vec<usize> = Vec::new();
+    // label -> (definition, enclosing function)
+    let mut labels: HashMap<i64, (usize, Option<usize>)> = HashMap::new();
+    // iterators created so far, per function
+    let mut iterators: Vec<HashSet<&str>> = vec![HashSet::new()];
+
+    for (i, instr) in program.body.iter().enumerate() {
+        let Instruction::Call { function } = instr else {
+            owner.push(functions.last().copied());
+            continue;
+        };
+
+        match function {
+            SoloFunction::FnStart(name, argc) => {
+                if *argc < 0 {
+                    error(
+                        i,
+                        format!("function '{}' has a negative argument count", name),
+                    );
+                }
+                owner.push(functions.last().copied());
+                functions.push(i);
+                iterators.push(HashSet::new());
+                continue;
+            }
+            SoloFunction::FnEnd() => {
+                owner.push(functions.last().copied());
+                if functions.pop().is_none() {
+                    error(i, "FnEnd without matching FnStart".to_string());
+                } else {
+                    iterators.pop();
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let current = functions.last().copied();
+        owner.push(current);
+
+        match function {
+            SoloFunction::Label(id) => {
+                if let Some((first, _)) = labels.get(id) {
+                    error(
+                        i,
+                        format!("label {} is already defined at instruction {}", id, first),
+                    );
+                } else {
+                    labels.insert(*id, (i, current));
+                }
+            }
+            SoloFunction::Return(_) if current.is_none() => {
+                error(i, "Return outside of a function".to_string());
+            }
+            SoloFunction::ForInStart(iter, _) => {
+                iterators.last_mut().unwrap().insert(iter);
+            }
+            SoloFunction::ForInNext(_, iter) => match iter {
+                Operand::Var(name) if iterators.last().unwrap().contains(name.as_str()) => {}
+                Operand::Var(name) => {
+                    error(
+                        i,
+                        format!(
+                            "ForInNext on '{}' which is not created by a ForInStart",
+                            name
+                        ),
+                    );
+                }
+                Operand::Const(c) => error(i, format!("ForInNext on constant {}", c)),
+            },
+            _ => {}
+        }
+    }
+
+    for start in functions {
+        error(start, "FnStart without matching FnEnd".to_string());
+    }
+
+    for (i, instr) in program.body.iter().enumerate() {
+        let Some(target) = instr.jump_target() else {
+            continue;
+        };
+        match labels.get(&target) {
+            None => error(i, format!("jump to undefined label {}", target)),
+            Some((_, function)) if *function != owner[i] => error(
+                i,
+                format!("jump to label {} defined in another function", target),
+            ),
+            _ => {}
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.index);
+    diagnostics
+}
@@ -25,25 +25,25 @@ pub enum TokenKind {
     True,
     False,
     Null,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    Switch,
+    Case,
+    Default, // even if Default is a Rust keyword, it does not generate a warning or error
+    Do,
 
     /*
     // future reserved keywords
-    Case,
     Debugger,
     Export,
     Super,
-    Catch,
-    Default, // even if Default is a Rust keyword, it does not generate a warning or error
     Extends,
-    Switch,
     Class,
-    Do,
-    Finally,
-    Throw,
     Const,
     Enum,
     Import,
-    Try,
     */
     // symbols
     SemiColon,
@@ -176,24 +176,24 @@ impl Lexer {
             "false" => TokenKind::False,
             "null" => TokenKind::Null,
             "undefined" => TokenKind::Undefined,
+            "throw" => TokenKind::Throw,
+            "try" => TokenKind::Try,
+            "catch" => TokenKind::Catch,
+            "finally" => TokenKind::Finally,
+            "switch" => TokenKind::Switch,
+            "case" => TokenKind::Case,
+            "default" => TokenKind::Default,
+            "do" => TokenKind::Do,
 
             /*
-            "case" => TokenKind::Case,
             "debugger" => TokenKind::Debugger,
             "export" => TokenKind::Export,
             "super" => TokenKind::Super,
-            "catch" => TokenKind::Catch,
-            "default" => TokenKind::Default,
             "extends" => TokenKind::Extends,
-            "switch" => TokenKind::Switch,
             "class" => TokenKind::Class,
-            "do" => TokenKind::Do,
-            "finally" => TokenKind::Finally,
-            "throw" => TokenKind::Throw,
             "const" => TokenKind::Const,
             "enum" => TokenKind::Enum,
             "import" => TokenKind::Import,
-            "try" => TokenKind::Try,
             */
             _ => TokenKind::Identifier,
         }
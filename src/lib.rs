@@ -4,3 +4,44 @@ pub mod ir;
 pub mod lexer;
 pub mod parser;
 pub mod vm;
+
+/// Source -> AST
+pub fn parse_source(source: &str) -> ast::Program {
+    let mut lex = lexer::Lexer {
+        source: source.to_string(),
+        cursor: lexer::Cursor { row: 0, line: 0 },
+        line: 0,
+        row: 0,
+        prev_cr: false,
+    };
+    let tokens = lex.walk();
+
+    let mut parser = parser::Parser {
+        tokens: Vec::new(),
+        pos: 0,
+        allow_in: true,
+        source: source.to_string(),
+    };
+    parser.parse(tokens)
+}
+
+/// AST -> IR
+pub fn compile_ast(program: ast::Program) -> ir::Program {
+    let mut compiler = ir::Compiler {
+        source: program,
+        pos: 0,
+        output: ir::Program { body: vec![] },
+        label_stack: 0,
+        loop_stack: vec![],
+        return_stack: vec![],
+        try_stack: vec![],
+    };
+    compiler.compile();
+    compiler.output
+}
+
+/// The whole pipeline: source -> AST -> IR -> bytecode. The debug info has
+/// the line table, the host fills in the file and the source if it keeps them
+pub fn compile_source(source: &str) -> vm::Bytecode {
+    vm::compile_to_bytecode(compile_ast(parse_source(source)))
+}
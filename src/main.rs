@@ -1,28 +1,78 @@
-use tinyjs::ir;
-use tinyjs::lexer;
-use tinyjs::parser;
 use tinyjs::vm;
 
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage: tinyjs [--compile out.tjbc | --disasm] <file.js | file.tjbc>";
+
 fn main() {
-    let source = "var i=0; while(i++<5){if (i==4) {break;} console.log('hi')} var b = {a: 16.2}; var c = undefined; var d = !{}\nfunction nen() {\n return 15-2;\n};".to_string();
-    let mut lex = lexer::Lexer {
-        source: source.clone(),
-        cursor: lexer::Cursor { row: 0, line: 0 },
-        line: 0,
-        row: 0,
-        prev_cr: false,
-    };
-
-    let tokens = lex.walk();
-
-    let mut parser = parser::Parser {
-        tokens: Vec::new(),
-        pos: 0,
-        allow_in: true,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.as_slice() {
+        [] => demo(),
+        [flag, output, input] if flag == "--compile" => {
+            let bc = compile_file(input);
+            let mut file = fs::File::create(output).unwrap_or_else(|err| {
+                fail(format!("cannot create {}: {}", output, err));
+            });
+            if let Err(err) = bc.write_to(&mut file) {
+                fail(format!("cannot write {}: {}", output, err));
+            }
+        }
+        [flag, input] if flag == "--disasm" => {
+            print!("{}", vm::disasm::disassemble(&load(input)));
+        }
+        [input] if !input.starts_with("--") => {
+            if let Err(err) = vm::VM::new(load(input)).run() {
+                fail(err.to_string());
+            }
+        }
+        _ => fail(USAGE.to_string()),
+    }
+}
+
+// Compile a script or read an already compiled file
+fn load(input: &str) -> vm::Bytecode {
+    let bytes = fs::read(input).unwrap_or_else(|err| {
+        fail(format!("cannot read {}: {}", input, err));
+    });
+    if bytes.starts_with(vm::format::MAGIC) {
+        let bc = vm::Bytecode::from_bytes(&bytes)
+            .unwrap_or_else(|err| fail(format!("{}: {}", input, err)));
+        let errors = vm::verify::verify(&bc);
+        if !errors.is_empty() {
+            let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            fail(format!("{}: invalid bytecode\n{}", input, lines.join("\n")));
+        }
+        bc
+    } else {
+        compile_file(input)
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn compile_file(path: &str) -> vm::Bytecode {
+    let source = fs::read_to_string(path).unwrap_or_else(|err| {
+        fail(format!("cannot read {}: {}", path, err));
+    });
+    let mut bc = tinyjs::compile_source(&source);
+    let lines = bc.debug.take().map(|debug| debug.lines).unwrap_or_default();
+    bc.debug = Some(vm::DebugInfo {
+        file: path.to_string(),
         source,
-    };
+        lines,
+    });
+    bc
+}
 
-    let program = parser.parse(tokens);
+// Without arguments: show every stage of the pipeline on a small program
+fn demo() {
+    let source = "var i=0; while(i++<5){if (i==4) {break;} console.log('hi')} var b = {a: 16.2}; var c = undefined; var d = !{}\nfunction nen() {\n return 15-2;\n};".to_string();
+    let program = tinyjs::parse_source(&source);
 
     println!("\nAST output:");
 
@@ -30,34 +80,28 @@ fn main() {
         println!("{:#?}", stmt);
     }
 
-    let mut compiler = ir::Compiler {
-        source: program,
-        pos: 0,
-        output: ir::Program { body: vec![] },
-        label_stack: 0,
-        loop_stack: vec![],
-        return_stack: vec![],
-    };
-    compiler.compile();
+    let ir = tinyjs::compile_ast(program);
 
     println!("\nIR output:");
 
-    for i in &compiler.output.body {
+    for i in &ir.body {
         println!("{:#?}", i);
     }
 
-    let bc = vm::compile_to_bytecode(compiler.output);
+    let mut bc = vm::compile_to_bytecode(ir);
+    if let Some(debug) = &mut bc.debug {
+        debug.source = source;
+    }
 
     println!("\nBytecode:");
-
-    for i in &bc.instructions {
-        println!("{:#?}", i);
-    }
+    print!("{}", vm::disasm::disassemble(&bc));
 
     println!("\nVM output:");
 
     let mut machine = vm::VM::new(bc);
-    machine.run();
+    if let Err(err) = machine.run() {
+        println!("{}", err);
+    }
     println!(
         "b: {:#?} (expect object with a: 16.2)",
         machine.get_variable("b")
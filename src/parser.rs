@@ -940,7 +940,22 @@ impl Parser {
         }
     }
 
+    // Position of the next token, for the statement that starts there
+    fn span(&self) -> ast::Span {
+        let tok = self.peek();
+        ast::Span {
+            line: tok.line as u32 + 1,
+            column: tok.col as u32 + 1,
+        }
+    }
+
     fn parse_statement(&mut self) -> ast::Stmt {
+        let span = self.span();
+        let stmt = self.parse_statement_kind();
+        ast::Stmt::Spanned(span, Box::new(stmt))
+    }
+
+    fn parse_statement_kind(&mut self) -> ast::Stmt {
         let tok = self.peek();
         match tok.kind {
             TokenKind::Function => {
@@ -959,7 +974,7 @@ impl Parser {
             TokenKind::If => {
                 return self.parse_if_statement();
             }
-            TokenKind::While | TokenKind::For => {
+            TokenKind::While | TokenKind::For | TokenKind::Do => {
                 return self.parse_iteration_statement();
             }
             TokenKind::Continue => {
@@ -974,6 +989,17 @@ impl Parser {
             TokenKind::With => {
                 return self.parse_with_statement();
             }
+            TokenKind::Throw => self.parse_throw_statement(),
+            TokenKind::Try => self.parse_try_statement(),
+            TokenKind::Switch => self.parse_switch_statement(),
+            TokenKind::Identifier
+                if self
+                    .tokens
+                    .get(self.pos + 1)
+                    .is_some_and(|next| next.kind == TokenKind::DoubleDot) =>
+            {
+                self.parse_labeled_statement()
+            }
             _ => {
                 // Not Function
                 let expr = self.parse_expression();
@@ -1236,6 +1262,29 @@ impl Parser {
             } else {
                 self.error("Expected '(' after the 'for' keyword".to_string());
             }
+        } else if self.check_kind(TokenKind::Do) {
+            let body = self.parse_statement();
+
+            if !self.check_kind(TokenKind::While) {
+                self.error(format!(
+                    "Expected 'while' after the body of 'do' but found '{}'",
+                    self.peek().content
+                ));
+            }
+            if !self.check_kind(TokenKind::OpenParen) {
+                self.error("Expected '(' after the 'while' keyword".to_string());
+            }
+            let cond = self.parse_expression();
+            if !self.check_kind(TokenKind::CloseParen) {
+                self.error("Expected ')' after '('".to_string());
+            }
+            // the semicolon is optional, even on the same line: `do x++; while (c) y()`
+            self.check_kind(TokenKind::SemiColon);
+
+            ast::Stmt::DoWhile {
+                body: Box::new(body),
+                cond,
+            }
         } else {
             self.error("No more options for iteration statement".to_string());
         }
@@ -1243,8 +1292,9 @@ impl Parser {
 
     fn parse_continue_statement(&mut self) -> ast::Stmt {
         if self.check_kind(TokenKind::Continue) {
+            let label = self.parse_jump_label();
             self.consume_semicolon_or_insert();
-            return ast::Stmt::Continue;
+            return ast::Stmt::Continue(label);
         }
 
         self.error(format!(
@@ -1255,8 +1305,9 @@ impl Parser {
 
     fn parse_break_statement(&mut self) -> ast::Stmt {
         if self.check_kind(TokenKind::Break) {
+            let label = self.parse_jump_label();
             self.consume_semicolon_or_insert();
-            return ast::Stmt::Break;
+            return ast::Stmt::Break(label);
         }
 
         self.error(format!(
@@ -1265,6 +1316,22 @@ impl Parser {
         ));
     }
 
+    // Label after `break` or `continue`, it must be on the same line
+    fn parse_jump_label(&mut self) -> Option<String> {
+        if self.peek().kind != TokenKind::Identifier || self.peek().line_terminator_before {
+            return None;
+        }
+        Some(self.advance().content)
+    }
+
+    fn parse_labeled_statement(&mut self) -> ast::Stmt {
+        let label = self.advance().content;
+        assert!(self.check_kind(TokenKind::DoubleDot));
+
+        let body = self.parse_statement();
+        ast::Stmt::Labeled(label, Box::new(body))
+    }
+
     fn parse_return_statement(&mut self) -> ast::Stmt {
         let expr: ast::Expr;
 
@@ -1310,6 +1377,118 @@ impl Parser {
         };
     }
 
+    fn parse_throw_statement(&mut self) -> ast::Stmt {
+        assert!(self.check_kind(TokenKind::Throw));
+
+        // no semicolon insertion here: `throw` alone on its line is an error
+        if self.peek().line_terminator_before {
+            self.error("Illegal newline after 'throw'".to_string());
+        }
+
+        let expr = self.parse_expression();
+        self.consume_semicolon_or_insert();
+        ast::Stmt::Throw(expr)
+    }
+
+    fn parse_try_statement(&mut self) -> ast::Stmt {
+        assert!(self.check_kind(TokenKind::Try));
+
+        if self.peek().kind != TokenKind::OpenCurly {
+            self.error(format!("Expected '{{' but found '{}'", self.peek().content));
+        }
+        let block = self.parse_block();
+
+        let mut catch = None;
+        if self.check_kind(TokenKind::Catch) {
+            if !self.check_kind(TokenKind::OpenParen) {
+                self.error(format!("Expected '(' but found '{}'", self.peek().content));
+            }
+            if self.peek().kind != TokenKind::Identifier {
+                self.error(format!(
+                    "Expected identifier but found '{}'",
+                    self.peek().content
+                ));
+            }
+            let name = self.peek().content.clone();
+            self.advance();
+            if !self.check_kind(TokenKind::CloseParen) {
+                self.error(format!("Expected ')' but found '{}'", self.peek().content));
+            }
+            if self.peek().kind != TokenKind::OpenCurly {
+                self.error(format!("Expected '{{' but found '{}'", self.peek().content));
+            }
+            catch = Some((name, Box::new(self.parse_block())));
+        }
+
+        let mut finally = None;
+        if self.check_kind(TokenKind::Finally) {
+            if self.peek().kind != TokenKind::OpenCurly {
+                self.error(format!("Expected '{{' but found '{}'", self.peek().content));
+            }
+            finally = Some(Box::new(self.parse_block()));
+        }
+
+        if catch.is_none() && finally.is_none() {
+            self.error("Missing catch or finally after try".to_string());
+        }
+
+        ast::Stmt::Try {
+            block: Box::new(block),
+            catch,
+            finally,
+        }
+    }
+
+    fn parse_switch_statement(&mut self) -> ast::Stmt {
+        assert!(self.check_kind(TokenKind::Switch));
+
+        if !self.check_kind(TokenKind::OpenParen) {
+            self.error(format!("Expected '(' but found '{}'", self.peek().content));
+        }
+        let discriminant = self.parse_expression();
+        if !self.check_kind(TokenKind::CloseParen) {
+            self.error(format!("Expected ')' but found '{}'", self.peek().content));
+        }
+        if !self.check_kind(TokenKind::OpenCurly) {
+            self.error(format!("Expected '{{' but found '{}'", self.peek().content));
+        }
+
+        let mut cases: Vec<ast::SwitchCase> = vec![];
+        while !self.check_kind(TokenKind::CloseCurly) {
+            let test = if self.check_kind(TokenKind::Case) {
+                Some(self.parse_expression())
+            } else if self.peek().kind == TokenKind::Default {
+                if cases.iter().any(|case| case.test.is_none()) {
+                    self.error("More than one default clause in switch statement".to_string());
+                }
+                self.advance();
+                None
+            } else {
+                self.error(format!(
+                    "Expected 'case', 'default' or '}}' but found '{}'",
+                    self.peek().content
+                ));
+            };
+            if !self.check_kind(TokenKind::DoubleDot) {
+                self.error(format!("Expected ':' but found '{}'", self.peek().content));
+            }
+
+            let mut body = vec![];
+            while !matches!(
+                self.peek().kind,
+                TokenKind::Case | TokenKind::Default | TokenKind::CloseCurly | TokenKind::EOF
+            ) {
+                body.push(self.parse_statement());
+            }
+            cases.push(ast::SwitchCase { test, body });
+        }
+
+        ast::Stmt::Switch {
+            discriminant,
+            cases,
+        }
+    }
+
     fn consume_semicolon_or_insert(&mut self) {
         if self.check_kind(TokenKind::SemiColon) {
             return;
@@ -1331,7 +1510,9 @@ impl Parser {
 
         while self.peek().kind != TokenKind::EOF {
             if self.peek().kind == TokenKind::Function {
-                body.push(ast::Stmt::Function(self.parse_function_declaration()));
+                let span = self.span();
+                let function = ast::Stmt::Function(self.parse_function_declaration());
+                body.push(ast::Stmt::Spanned(span, Box::new(function)));
             } else {
                 body.push(self.parse_statement());
             }
@@ -1,13 +1,28 @@
-use crate::ir::{Const, Function, Instruction, Operand, Program, SoloFunction};
+use crate::ir::{Const, Function, Instruction, Operand, Program, SoloFunction, is_temporary};
+use error::{ErrorKind, RuntimeError, StackFrame};
+use object::{Attributes, ForInIterator, Object};
 
-#[derive(Debug, Clone, PartialEq)]
+pub mod disasm;
+pub mod error;
+pub mod format;
+pub mod number;
+pub mod object;
+pub mod peephole;
+pub mod verify;
+
+/// Instructions refer to constants and names by their index in the pools of
+/// the `Bytecode`, so they are small and cheap to copy
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
-    /// Push a constant onto the stack
-    Const(Const),
-    /// Load a variable onto the stack
-    Load(String),
+    /// Push a constant (index in the constant pool) onto the stack
+    Const(u32),
+    /// Load a variable (index in the name table) onto the stack
+    Load(u32),
+    /// Push the name of the type of a variable (index in the name table),
+    /// "undefined" if it is not declared
+    TypeofVar(u32),
     /// Store top of stack into variable (pops the value)
-    Store(String),
+    Store(u32),
     /// Pop and discard top of stack
     Pop,
     /// Duplicate top of stack
@@ -26,14 +41,17 @@ pub enum OpCode {
     // Comparison operations (pop 2, push 1 bool)
     Eq,
     Ne,
+    /// Equality without type conversion, for the cases of a switch
+    StrictEq,
     Lt,
     Gt,
     Le,
     Ge,
 
     // Unary operations (pop 1, push 1)
-    Neg, // arithmetic negation
-    Not, // logical negation
+    Neg,    // arithmetic negation
+    Not,    // logical negation
+    Typeof, // name of the type of the value
 
     // Control flow (labels are resolved to offsets during compilation)
     /// Unconditional jump to offset
@@ -45,7 +63,7 @@ pub enum OpCode {
     // Function operations
     /// Define function start: name, arg_count, body_offset
     FnStart {
-        name: String,
+        name: u32,
         argc: u8,
         body_offset: i32,
     },
@@ -53,13 +71,18 @@ pub enum OpCode {
     FnEnd,
     /// Call function by name with N arguments (args are on stack)
     Call {
-        name: String,
+        name: u32,
         argc: u8,
     },
     /// Call top of stack as a function (argc args on stack below)
     CallDynamic {
         argc: u8,
     },
+    /// Call a method: the object, the function and the argc args are on the
+    /// stack, the object is passed to the function as `this`
+    CallMethod {
+        argc: u8,
+    },
     /// Return from function (optionally with value from stack)
     Return {
         has_value: bool,
@@ -73,7 +96,7 @@ pub enum OpCode {
 
     // Object/Array operations
     /// Delete variable
-    Kill(String),
+    Kill(u32),
     /// Create object from N key-value pairs (2*N values on stack)
     MakeObject {
         pairs: u16,
@@ -88,6 +111,12 @@ pub enum OpCode {
     SetProp,
     /// Check if property exists (obj and key on stack, pushes bool)
     HasProp,
+    /// Delete property: delete obj[prop] (obj and prop on stack, pushes whether
+    /// it could be deleted)
+    Delete,
+    /// Delete a variable, or the property of a with object it resolves to
+    /// (pushes whether it could be deleted)
+    DeleteVar(u32),
 
     // Iterator operations for for..in
     /// Start iteration, pushes iterator onto stack
@@ -95,6 +124,15 @@ pub enum OpCode {
     /// Get next key from iterator (iterator on stack), pushes key or undefined
     ForInNext,
 
+    // Exception handling
+    /// Install a handler at offset: an exception raised until the matching
+    /// EndTry unwinds to it and jumps there with the exception on the stack
+    Try(i32),
+    /// Remove the last installed handler
+    EndTry,
+    /// Raise top of stack as an exception
+    Throw,
+
     Nop,
     Halt,
 }
@@ -103,12 +141,59 @@ pub enum OpCode {
 #[derive(Debug)]
 pub struct Bytecode {
     pub instructions: Vec<OpCode>,
+    /// Constant pool, used by `Const`
+    pub constants: Vec<Const>,
+    /// Name table, used by `Load`, `Store`, `Kill`, `DeleteVar`, `TypeofVar`,
+    /// `Call` and `FnStart`
+    pub names: Vec<String>,
+    /// Names of the compiler temporaries: only the `Load`s of the code that
+    /// stores them read them, never the host or a call through their name
+    pub temporaries: std::collections::HashSet<u32>,
+    /// Where the program comes from, only used for diagnostics
+    pub debug: Option<DebugInfo>,
+}
+
+/// Source information kept alongside the bytecode
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugInfo {
+    /// Name of the source file
+    pub file: String,
+    /// Full source text
+    pub source: String,
+    /// Source position (1-based line and column) of the instructions, sorted by
+    /// pc. An entry covers every instruction up to the next one
+    pub lines: Vec<LineEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineEntry {
+    pub pc: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl DebugInfo {
+    /// (line, column) of the instruction at `pc`, if known
+    pub fn position(&self, pc: usize) -> Option<(u32, u32)> {
+        let next = self.lines.partition_point(|e| e.pc as usize <= pc);
+        let entry = self.lines.get(next.checked_sub(1)?)?;
+        Some((entry.line, entry.column))
+    }
+
+    /// Text of a source line
+    pub fn source_line(&self, line: u32) -> Option<&str> {
+        self.source.lines().nth((line as usize).checked_sub(1)?)
+    }
 }
 
 impl Bytecode {
     pub fn new() -> Self {
         Self {
             instructions: Vec::new(),
+            constants: Vec::new(),
+            names: Vec::new(),
+            temporaries: std::collections::HashSet::new(),
+            debug: None,
         }
     }
 
@@ -116,6 +201,40 @@ impl Bytecode {
         self.instructions.push(op);
     }
 
+    /// Index of a constant in the pool, added if needed
+    pub fn constant_index(&mut self, c: Const) -> u32 {
+        match self.constants.iter().position(|k| *k == c) {
+            Some(index) => index as u32,
+            None => {
+                self.constants.push(c);
+                self.constants.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Index of a name in the name table, added if needed
+    pub fn name_index(&mut self, name: &str) -> u32 {
+        match self.names.iter().position(|n| n == name) {
+            Some(index) => index as u32,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() as u32 - 1
+            }
+        }
+    }
+
+    pub fn constant(&self, index: u32) -> &Const {
+        &self.constants[index as usize]
+    }
+
+    pub fn name(&self, index: u32) -> &str {
+        &self.names[index as usize]
+    }
+
+    pub fn is_temporary(&self, index: u32) -> bool {
+        self.temporaries.contains(&index)
+    }
+
     pub fn pos(&self) -> usize {
         self.instructions.len()
     }
@@ -123,11 +242,12 @@ impl Bytecode {
     /// Patch a jump instruction at position with target offset
     pub fn patch_jump(&mut self, pos: usize, target: i32) {
         match self.instructions[pos] {
-            OpCode::Jump(_) | OpCode::JumpIf(_) | OpCode::JumpIfNot(_) => {
+            OpCode::Jump(_) | OpCode::JumpIf(_) | OpCode::JumpIfNot(_) | OpCode::Try(_) => {
                 self.instructions[pos] = match self.instructions[pos] {
                     OpCode::Jump(_) => OpCode::Jump(target),
                     OpCode::JumpIf(_) => OpCode::JumpIf(target),
                     OpCode::JumpIfNot(_) => OpCode::JumpIfNot(target),
+                    OpCode::Try(_) => OpCode::Try(target),
                     _ => unreachable!(),
                 };
             }
@@ -149,8 +269,6 @@ struct BytecodeCompiler {
     label_map: std::collections::HashMap<i64, usize>,
     /// Positions of jump instructions that need backpatching (label_id)
     pending_jumps: Vec<(usize, i64)>,
-    /// Pending function body jumps: (position_of_jump, label_to_emit_at_end)
-    pending_fn_jumps: Vec<(usize, i64)>,
     /// Function entry points: name -> bytecode position
     function_entries: std::collections::HashMap<String, usize>,
     /// Counter for generating unique end-of-function labels
@@ -159,6 +277,14 @@ struct BytecodeCompiler {
     fn_end_label_stack: Vec<i64>,
     /// Map function end label -> FnStart position (for backpatching body_offset)
     fn_start_positions: std::collections::HashMap<i64, usize>,
+    /// Indices of the names already in the name table
+    name_ids: std::collections::HashMap<String, u32>,
+    /// Indices of the string and number constants already in the pool (numbers
+    /// by their bits)
+    string_ids: std::collections::HashMap<String, u32>,
+    number_ids: std::collections::HashMap<u64, u32>,
+    /// Line table being built from the `Position`s of the IR
+    lines: Vec<LineEntry>,
 }
 
 impl BytecodeCompiler {
@@ -167,12 +293,54 @@ impl BytecodeCompiler {
             bytecode: Bytecode::new(),
             label_map: std::collections::HashMap::new(),
             pending_jumps: Vec::new(),
-            pending_fn_jumps: Vec::new(),
             function_entries: std::collections::HashMap::new(),
             label_counter: 10000, // Start high to avoid conflicts with IR labels
             fn_end_label_stack: Vec::new(),
             fn_start_positions: std::collections::HashMap::new(),
+            name_ids: std::collections::HashMap::new(),
+            string_ids: std::collections::HashMap::new(),
+            number_ids: std::collections::HashMap::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(&index) = self.name_ids.get(name) {
+            return index;
+        }
+        let index = self.bytecode.names.len() as u32;
+        self.bytecode.names.push(name.to_string());
+        self.name_ids.insert(name.to_string(), index);
+        // the `__tN` of the IR, see `ir::dce`
+        if is_temporary(name) {
+            self.bytecode.temporaries.insert(index);
         }
+        index
+    }
+
+    fn constant(&mut self, c: Const) -> u32 {
+        let cached = match &c {
+            Const::String(s) => self.string_ids.get(s),
+            Const::Number(n) => self.number_ids.get(&n.to_bits()),
+            // few of them, the pool lookup is enough
+            _ => return self.bytecode.constant_index(c),
+        };
+        if let Some(&index) = cached {
+            return index;
+        }
+        let index = self.bytecode.constants.len() as u32;
+        match &c {
+            Const::String(s) => self.string_ids.insert(s.clone(), index),
+            Const::Number(n) => self.number_ids.insert(n.to_bits(), index),
+            _ => unreachable!(),
+        };
+        self.bytecode.constants.push(c);
+        index
+    }
+
+    fn emit_store(&mut self, name: &str) {
+        let name = self.name(name);
+        self.bytecode.emit(OpCode::Store(name));
     }
 
     fn new_label(&mut self) -> i64 {
@@ -190,20 +358,45 @@ impl BytecodeCompiler {
         self.resolve_jumps();
 
         self.bytecode.emit(OpCode::Halt);
+
+        // the host fills in the file and the source if it has them
+        if !self.lines.is_empty() {
+            self.bytecode.debug = Some(DebugInfo {
+                file: String::new(),
+                source: String::new(),
+                lines: std::mem::take(&mut self.lines),
+            });
+        }
+    }
+
+    fn mark_position(&mut self, line: u32, column: u32) {
+        let pc = self.bytecode.pos() as u32;
+        // a statement without code of its own (block, empty statement...)
+        if self.lines.last().is_some_and(|last| last.pc == pc) {
+            self.lines.pop();
+        }
+        if self
+            .lines
+            .last()
+            .is_some_and(|last| (last.line, last.column) == (line, column))
+        {
+            return;
+        }
+        self.lines.push(LineEntry { pc, line, column });
     }
 
     fn compile_instruction(&mut self, instr: Instruction) {
         match instr {
             Instruction::Assign { dest, src } => {
                 self.emit_operand(src);
-                self.bytecode.emit(OpCode::Store(dest));
+                self.emit_store(&dest);
             }
             Instruction::Call { function } => {
                 self.compile_solo_function(function);
             }
             Instruction::Classic { dest, function } => {
                 self.compile_function(function);
-                self.bytecode.emit(OpCode::Store(dest));
+                self.emit_store(&dest);
             }
         }
     }
@@ -211,9 +404,11 @@ impl BytecodeCompiler {
     fn emit_operand(&mut self, op: Operand) {
         match op {
             Operand::Var(name) => {
+                let name = self.name(&name);
                 self.bytecode.emit(OpCode::Load(name));
             }
             Operand::Const(c) => {
+                let c = self.constant(c);
                 self.bytecode.emit(OpCode::Const(c));
             }
         }
@@ -269,6 +464,11 @@ impl BytecodeCompiler {
                 self.emit_operand(b);
                 self.bytecode.emit(OpCode::Ne);
             }
+            StrictEqual(a, b) => {
+                self.emit_operand(a);
+                self.emit_operand(b);
+                self.bytecode.emit(OpCode::StrictEq);
+            }
             LessThan(a, b) => {
                 self.emit_operand(a);
                 self.emit_operand(b);
@@ -289,6 +489,25 @@ impl BytecodeCompiler {
                 self.emit_operand(b);
                 self.bytecode.emit(OpCode::Ge);
             }
+            In(key, obj) => {
+                self.emit_operand(obj);
+                self.emit_operand(key);
+                self.bytecode.emit(OpCode::HasProp);
+            }
+            GetProp(obj, key) => {
+                self.emit_operand(obj);
+                self.emit_operand(key);
+                self.bytecode.emit(OpCode::GetProp);
+            }
+            // typeof of an undeclared variable is "undefined", not an error
+            Typeof(Operand::Var(name)) => {
+                let name = self.name(&name);
+                self.bytecode.emit(OpCode::TypeofVar(name));
+            }
+            Typeof(op) => {
+                self.emit_operand(op);
+                self.bytecode.emit(OpCode::Typeof);
+            }
         }
     }
 
@@ -312,6 +531,7 @@ impl BytecodeCompiler {
             }
             Kill(op) => {
                 if let Operand::Var(name) = op {
+                    let name = self.name(&name);
                     self.bytecode.emit(OpCode::Kill(name));
                 }
             }
@@ -319,13 +539,11 @@ impl BytecodeCompiler {
                 let end_label = self.new_label();
                 self.fn_end_label_stack.push(end_label);
 
-                let jump_pos = self.bytecode.pos();
-                self.bytecode.emit(OpCode::Jump(0)); // Will be backpatched
-                self.pending_fn_jumps.push((jump_pos, end_label));
-
+                // FnStart defines the function and jumps over its body
                 let fn_start_pos = self.bytecode.pos();
+                let name_index = self.name(&name);
                 self.bytecode.emit(OpCode::FnStart {
-                    name: name.clone(),
+                    name: name_index,
                     argc: argc as u8,
                     body_offset: 0, // Will be backpatched
                 });
@@ -335,6 +553,7 @@ impl BytecodeCompiler {
             }
             FnEnd() => {
                 self.bytecode.emit(OpCode::Return { has_value: false });
+                self.bytecode.emit(OpCode::FnEnd);
 
                 let end_label = self
                     .fn_end_label_stack
@@ -360,42 +579,83 @@ impl BytecodeCompiler {
             }
             FnCall(name, args) => {
                 self.emit_operand(args); // naive to just push without verifying - can crash the VM
+                let name = self.name(&name);
                 self.bytecode.emit(OpCode::Call { name, argc: 1 });
             }
             MethodCall(obj, method, args) => {
+                // the object stays below the method, as `this`
                 self.emit_operand(obj);
-                self.bytecode
-                    .emit(OpCode::Const(crate::ir::Const::String(method)));
+                self.bytecode.emit(OpCode::Dup);
+                let method = self.constant(Const::String(method));
+                self.bytecode.emit(OpCode::Const(method));
                 self.bytecode.emit(OpCode::GetProp);
                 self.emit_operand(args);
-                self.bytecode.emit(OpCode::CallDynamic { argc: 1 });
+                self.bytecode.emit(OpCode::CallMethod { argc: 1 });
             }
             Call(func, args) => {
                 self.emit_operand(func);
                 self.emit_operand(args);
                 self.bytecode.emit(OpCode::CallDynamic { argc: 1 });
             }
+            CallResult(dest) => {
+                // every call pushes the value it returns
+                self.emit_store(&dest);
+            }
             ForInStart(iter_var, obj) => {
                 self.emit_operand(obj);
                 self.bytecode.emit(OpCode::ForInStart);
-                self.bytecode.emit(OpCode::Store(iter_var));
+                self.emit_store(&iter_var);
             }
             ForInNext(key_var, iter) => {
                 self.emit_operand(iter);
                 self.bytecode.emit(OpCode::ForInNext);
-                self.bytecode.emit(OpCode::Store(key_var));
+                self.emit_store(&key_var);
             }
             MakeObject(var_name, props) => {
                 // Push all key-value pairs onto the stack
                 for (key, value) in &props {
-                    self.bytecode
-                        .emit(OpCode::Const(Const::String(key.clone())));
+                    let key = self.constant(Const::String(key.clone()));
+                    self.bytecode.emit(OpCode::Const(key));
                     self.emit_operand(value.clone());
                 }
                 self.bytecode.emit(OpCode::MakeObject {
                     pairs: props.len() as u16,
                 });
-                self.bytecode.emit(OpCode::Store(var_name));
+                self.emit_store(&var_name);
+            }
+            Position(line, column) => self.mark_position(line, column),
+            TryStart(label) => {
+                let try_pos = self.bytecode.pos();
+                self.bytecode.emit(OpCode::Try(0)); // Will be backpatched
+                self.pending_jumps.push((try_pos, label));
+            }
+            TryEnd() => {
+                self.bytecode.emit(OpCode::EndTry);
+            }
+            Catch(name) => {
+                // the VM pushes the exception before jumping to the handler
+                self.emit_store(&name);
+            }
+            Throw(op) => {
+                self.emit_operand(op);
+                self.bytecode.emit(OpCode::Throw);
+            }
+            Delete(dest, obj, key) => {
+                self.emit_operand(obj);
+                self.emit_operand(key);
+                self.bytecode.emit(OpCode::Delete);
+                self.emit_store(&dest);
+            }
+            DeleteVar(dest, name) => {
+                let name = self.name(&name);
+                self.bytecode.emit(OpCode::DeleteVar(name));
+                self.emit_store(&dest);
+            }
+            SetProp(obj, key, value) => {
+                self.emit_operand(obj);
+                self.emit_operand(key);
+                self.emit_operand(value);
+                self.bytecode.emit(OpCode::SetProp);
             }
             MakeArray(var_name, elements) => {
                 // Push all elements onto the stack
@@ -405,7 +665,7 @@ impl BytecodeCompiler {
                 self.bytecode.emit(OpCode::MakeArray {
                     len: elements.len() as u16,
                 });
-                self.bytecode.emit(OpCode::Store(var_name));
+                self.emit_store(&var_name);
             }
         }
     }
@@ -421,16 +681,6 @@ impl BytecodeCompiler {
             }
         }
 
-        // Resolve function body jumps (skip over function definitions)
-        for (pos, end_label) in &self.pending_fn_jumps {
-            if let Some(&target) = self.label_map.get(end_label) {
-                let offset = target as i32 - *pos as i32;
-                self.bytecode.patch_jump(*pos, offset);
-            } else {
-                panic!("Undefined function end label: {}", end_label);
-            }
-        }
-
         // Resolve function body offsets for FnStart instructions
         self.resolve_fn_body_offsets();
     }
@@ -440,12 +690,9 @@ impl BytecodeCompiler {
             if let Some(&end_pos) = self.label_map.get(end_label) {
                 let body_offset = end_pos as i32 - *fn_start_pos as i32;
                 // Patch the FnStart instruction at fn_start_pos
-                if let OpCode::FnStart {
-                    ref name, ref argc, ..
-                } = self.bytecode.instructions[*fn_start_pos]
+                if let OpCode::FnStart { name, argc, .. } =
+                    self.bytecode.instructions[*fn_start_pos]
                 {
-                    let name = name.clone();
-                    let argc = *argc;
                     self.bytecode.instructions[*fn_start_pos] = OpCode::FnStart {
                         name,
                         argc,
@@ -465,6 +712,10 @@ impl BytecodeCompiler {
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// What a native function returns, its error is raised as an error object of
+/// that kind
+pub type NativeResult = Result<Value, (ErrorKind, String)>;
+
 #[derive(Debug, Clone)]
 pub enum Value {
     Number(f64),
@@ -472,10 +723,18 @@ pub enum Value {
     Bool(bool),
     Null,
     Undefined,
-    Object(Rc<RefCell<std::collections::HashMap<String, Box<Value>>>>),
+    Object(Rc<RefCell<Object>>),
     Array(Vec<Value>),
-    Function { name: String, addr: usize, argc: u8 },
-    NativeFunction(fn(&mut VM, &[Value]) -> Result<Value, String>),
+    Function {
+        name: String,
+        addr: usize,
+        argc: u8,
+    },
+    NativeFunction(fn(&mut VM, &[Value]) -> NativeResult),
+    /// Native function that also gets the object it is called on
+    NativeMethod(fn(&mut VM, &Value, &[Value]) -> NativeResult),
+    /// State of a for..in loop, held by a variable of the frame running it
+    Iterator(Rc<RefCell<ForInIterator>>),
 }
 
 impl Value {
@@ -489,6 +748,44 @@ impl Value {
         }
     }
 
+    fn is_callable(&self) -> bool {
+        matches!(
+            self,
+            Value::Function { .. } | Value::NativeFunction(_) | Value::NativeMethod(_)
+        )
+    }
+
+    /// Whether the value is of the Object type of the spec, functions included
+    fn is_object(&self) -> bool {
+        !matches!(
+            self,
+            Value::Number(_) | Value::String(_) | Value::Bool(_) | Value::Null | Value::Undefined
+        )
+    }
+
+    /// Result of the typeof operator (ES1 section 11.4.3)
+    pub fn type_of(&self) -> &'static str {
+        match self {
+            Value::Undefined => "undefined",
+            Value::Null => "object",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function { .. } | Value::NativeFunction(_) | Value::NativeMethod(_) => {
+                "function"
+            }
+            Value::Object(_) | Value::Array(_) | Value::Iterator(_) => "object",
+        }
+    }
+
+    // How a value is shown in error messages
+    fn describe(&self) -> String {
+        match self {
+            Value::String(s) => format!("{:?}", s),
+            _ => self.to_string(),
+        }
+    }
+
     fn to_number(&self) -> f64 {
         match self {
             Value::Number(n) => *n,
@@ -499,7 +796,7 @@ impl Value {
                     0.0
                 }
             }
-            Value::String(s) => s.parse().unwrap_or(f64::NAN),
+            Value::String(s) => number::string_to_number(s),
             Value::Null => 0.0,
             Value::Undefined => f64::NAN,
             _ => f64::NAN,
@@ -509,19 +806,7 @@ impl Value {
     fn to_string(&self) -> String {
         match self {
             Value::String(s) => s.clone(),
-            Value::Number(n) => {
-                if n.is_nan() {
-                    "NaN".to_string()
-                } else if n.is_infinite() {
-                    if *n > 0.0 {
-                        "Infinity".to_string()
-                    } else {
-                        "-Infinity".to_string()
-                    }
-                } else {
-                    format!("{}", n)
-                }
-            }
+            Value::Number(n) => number::number_to_string(*n),
             Value::Bool(b) => b.to_string(),
             Value::Null => "null".to_string(),
             Value::Undefined => "undefined".to_string(),
@@ -530,6 +815,123 @@ impl Value {
     }
 }
 
+/// Type preferred by ToPrimitive, which of `valueOf` and `toString` is
+/// tried first
+#[derive(Clone, Copy)]
+enum Hint {
+    String,
+    Number,
+}
+
+/// Equality of two values of the same type, false for different types. Objects
+/// are equal when they are the same object; arrays are copied on assignment, so
+/// two arrays are equal when their elements are
+fn strict_equals(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        // NaN is not equal to itself, +0 is equal to -0
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Null, Value::Null) | (Value::Undefined, Value::Undefined) => true,
+        (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+        (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| strict_equals(a, b))
+        }
+        (Value::Function { addr: a, .. }, Value::Function { addr: b, .. }) => a == b,
+        (Value::NativeFunction(a), Value::NativeFunction(b)) => std::ptr::fn_addr_eq(*a, *b),
+        (Value::NativeMethod(a), Value::NativeMethod(b)) => std::ptr::fn_addr_eq(*a, *b),
+        _ => false,
+    }
+}
+
+struct CallFrame {
+    /// Where to continue in the caller
+    return_addr: usize,
+    /// Name of the called function
+    function: String,
+}
+
+/// Exception handler installed by `Try`, with the state to unwind to
+struct Handler {
+    /// Address of the handler code
+    pc: usize,
+    stack_len: usize,
+    call_depth: usize,
+    locals_len: usize,
+    scope_len: usize,
+}
+
+fn console_log(_: &mut VM, args: &[Value]) -> NativeResult {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    println!("{}", args.join(" "));
+    Ok(Value::Undefined)
+}
+
+fn parse_float(_: &mut VM, args: &[Value]) -> NativeResult {
+    let s = args.first().map(|arg| arg.to_string()).unwrap_or_default();
+    Ok(Value::Number(number::parse_float(&s)))
+}
+
+// Number.prototype.toString(radix) (ES1 section 15.7.4.2)
+fn number_to_string(_: &mut VM, this: &Value, args: &[Value]) -> NativeResult {
+    let Value::Number(n) = this else {
+        return Err((
+            ErrorKind::TypeError,
+            "Number.prototype.toString called on a value that is not a number".to_string(),
+        ));
+    };
+    let radix = match args.first() {
+        None | Some(Value::Undefined) => 10.0,
+        Some(radix) => radix.to_number().trunc(),
+    };
+    if !(2.0..=36.0).contains(&radix) {
+        return Err((
+            ErrorKind::RangeError,
+            "toString() radix must be between 2 and 36".to_string(),
+        ));
+    }
+    Ok(Value::String(number::number_to_radix_string(
+        *n,
+        radix as u32,
+    )))
+}
+
+fn number_prototype() -> Object {
+    let mut prototype = Object::new();
+    prototype.define(
+        "toString".to_string(),
+        Value::NativeMethod(number_to_string),
+        Attributes::DONT_ENUM,
+    );
+    prototype
+}
+
+// The Math object with its constants (ES1 section 15.8.1)
+fn math_object() -> Object {
+    use std::f64::consts;
+
+    let mut math = Object::new();
+    for (name, value) in [
+        ("E", consts::E),
+        ("LN10", consts::LN_10),
+        ("LN2", consts::LN_2),
+        ("LOG2E", consts::LOG2_E),
+        ("LOG10E", consts::LOG10_E),
+        ("PI", consts::PI),
+        ("SQRT1_2", consts::FRAC_1_SQRT_2),
+        ("SQRT2", consts::SQRT_2),
+    ] {
+        math.define(name.to_string(), Value::Number(value), Attributes::CONSTANT);
+    }
+    math
+}
+
+/// How deep functions called from inside an instruction (conversions) can
+/// nest. Each level takes tens of kilobytes of native stack in debug builds,
+/// this fits in the 2 MB of a spawned thread
+const MAX_NESTED_CALLS: usize = 32;
+
 /// VM execution context
 pub struct VM {
     /// Global variables
@@ -540,388 +942,820 @@ pub struct VM {
     stack: Vec<Value>,
     /// Program counter
     pc: usize,
-    /// Call stack (return addresses)
-    call_stack: Vec<usize>,
+    /// Call stack, one frame per function being executed
+    call_stack: Vec<CallFrame>,
     /// Scope chain for with statements
     scope_chain: Vec<Value>,
+    /// Exception handlers, innermost last
+    handlers: Vec<Handler>,
     /// Bytecode being executed
     bytecode: Bytecode,
-    /// Iterator storage for for..in
-    iterators: std::collections::HashMap<String, Vec<String>>,
+    /// Where the properties of numbers are looked up
+    number_prototype: Rc<RefCell<Object>>,
+    /// Number of `call_value` running inside each other, each one uses the
+    /// native stack
+    nested_calls: usize,
+    /// `prototype` objects of the script functions by address, made on first
+    /// use
+    prototypes: std::collections::HashMap<usize, Rc<RefCell<Object>>>,
 }
 
 impl VM {
     pub fn new(bytecode: Bytecode) -> Self {
+        let mut console = Object::new();
+        console.insert("log".to_string(), Value::NativeFunction(console_log));
+        let mut globals = std::collections::HashMap::new();
+        globals.insert(
+            "console".to_string(),
+            Value::Object(Rc::new(RefCell::new(console))),
+        );
+        globals.insert(
+            "Math".to_string(),
+            Value::Object(Rc::new(RefCell::new(math_object()))),
+        );
+        globals.insert("parseFloat".to_string(), Value::NativeFunction(parse_float));
+
         Self {
-            globals: std::collections::HashMap::new(),
+            globals,
             locals: vec![std::collections::HashMap::new()],
             stack: Vec::new(),
             pc: 0,
             call_stack: Vec::new(),
             scope_chain: Vec::new(),
+            handlers: Vec::new(),
             bytecode,
-            iterators: std::collections::HashMap::new(),
+            number_prototype: Rc::new(RefCell::new(number_prototype())),
+            nested_calls: 0,
+            prototypes: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Source position (line, column) of the instruction being executed, if the
+    /// bytecode has a line table
+    pub fn position(&self) -> Option<(u32, u32)> {
+        let debug = self.bytecode.debug.as_ref()?;
+        debug.position(self.pc.checked_sub(1)?)
+    }
+
+    /// Call stack from the innermost frame, each with the instruction it is
+    /// executing
+    fn stack_trace(&self) -> Vec<StackFrame> {
+        let position = |pc: usize| self.bytecode.debug.as_ref()?.position(pc);
+        let mut pc = self.pc.saturating_sub(1);
+        let mut frames = Vec::new();
+        for frame in self.call_stack.iter().rev() {
+            frames.push(StackFrame {
+                function: Some(frame.function.clone()),
+                pc,
+                position: position(pc),
+            });
+            // the call instruction
+            pc = frame.return_addr.saturating_sub(1);
+        }
+        frames.push(StackFrame {
+            function: None,
+            pc,
+            position: position(pc),
+        });
+        frames
+    }
+
+    /// Raise a value, with the current stack trace
+    #[cold]
+    fn throw(&self, value: Value) -> RuntimeError {
+        RuntimeError {
+            value,
+            stack: self.stack_trace(),
+            file: self
+                .bytecode
+                .debug
+                .as_ref()
+                .map(|debug| debug.file.clone())
+                .unwrap_or_default(),
         }
     }
 
-    pub fn run(&mut self) -> Value {
+    /// Build an error object of the given kind, with the current stack trace
+    #[cold]
+    fn error(&self, kind: ErrorKind, message: impl Into<String>) -> RuntimeError {
+        let mut error = self.throw(Value::Undefined);
+        let mut object = Object::new();
+        object.insert("name".to_string(), Value::String(kind.name().to_string()));
+        object.insert("message".to_string(), Value::String(message.into()));
+        error.value = Value::Object(Rc::new(RefCell::new(object)));
+        let stack = error.trace();
+        if let Value::Object(object) = &error.value {
+            object.borrow_mut().define(
+                "stack".to_string(),
+                Value::String(stack),
+                Attributes::DONT_ENUM,
+            );
+        }
+        error
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.error(ErrorKind::InternalError, "stack underflow")),
+        }
+    }
+
+    /// Run the program until it halts, returns the value left on the stack or
+    /// the error that stopped it
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
         loop {
-            let op = self.bytecode.instructions[self.pc].clone();
-            self.pc += 1;
+            match self.step() {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => {}
+                Err(error) => self.unwind(error)?,
+            }
+        }
+    }
 
-            match op {
-                OpCode::Const(c) => {
-                    let val = self.const_to_value(c);
-                    self.stack.push(val);
-                }
-                OpCode::Load(name) => {
-                    let val = self.get_variable(&name);
-                    self.stack.push(val);
-                }
-                OpCode::Store(name) => {
-                    let val = self.stack.pop().unwrap();
-                    self.set_variable(name, val);
-                }
-                OpCode::Pop => {
-                    self.stack.pop();
-                }
-                OpCode::Dup => {
-                    let val = self.stack.last().unwrap().clone();
-                    self.stack.push(val);
-                }
-                OpCode::Swap => {
-                    let len = self.stack.len();
-                    self.stack.swap(len - 1, len - 2);
-                }
-                OpCode::Add => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    let result = self.add_values(a, b);
-                    self.stack.push(result);
-                }
-                OpCode::Sub => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Number(a - b));
-                }
-                OpCode::Mul => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Number(a * b));
-                }
-                OpCode::Div => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Number(a / b));
-                }
-                OpCode::Mod => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Number(a % b));
-                }
-                OpCode::Pow => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Number(a.powf(b)));
-                }
-                OpCode::Eq => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(Value::Bool(self.values_equal(a, b)));
-                }
-                OpCode::Ne => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(Value::Bool(!self.values_equal(a, b)));
-                }
-                OpCode::Lt => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Bool(a < b));
-                }
-                OpCode::Gt => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Bool(a > b));
-                }
-                OpCode::Le => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Bool(a <= b));
-                }
-                OpCode::Ge => {
-                    let b = self.stack.pop().unwrap().to_number();
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Bool(a >= b));
-                }
-                OpCode::Neg => {
-                    let a = self.stack.pop().unwrap().to_number();
-                    self.stack.push(Value::Number(-a));
-                }
-                OpCode::Not => {
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(Value::Bool(!a.is_truthy()));
+    /// Go back to the state of the innermost handler and jump to it with the
+    /// exception on the stack, gives the error back if there is no handler
+    #[cold]
+    fn unwind(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        let Some(handler) = self.handlers.pop() else {
+            return Err(error);
+        };
+        self.call_stack.truncate(handler.call_depth);
+        self.locals.truncate(handler.locals_len);
+        self.scope_chain.truncate(handler.scope_len);
+        self.stack.truncate(handler.stack_len);
+        self.stack.push(error.value);
+        self.pc = handler.pc;
+        Ok(())
+    }
+
+    // Execute one instruction, returns the result of the program once it ends
+    #[inline(always)]
+    fn step(&mut self) -> Result<Option<Value>, RuntimeError> {
+        let Some(&op) = self.bytecode.instructions.get(self.pc) else {
+            return Err(self.error(
+                ErrorKind::InternalError,
+                format!("jump to {} is out of the code", self.pc),
+            ));
+        };
+        self.pc += 1;
+
+        match op {
+            OpCode::Const(c) => {
+                let val = self.const_to_value(self.bytecode.constant(c));
+                self.stack.push(val);
+            }
+            OpCode::Load(name) => {
+                let name = self.bytecode.name(name);
+                let Some(val) = self.lookup(name) else {
+                    return Err(self.error(
+                        ErrorKind::ReferenceError,
+                        format!("{} is not defined", name),
+                    ));
+                };
+                self.stack.push(val);
+            }
+            OpCode::Store(name) => {
+                let val = self.pop()?;
+                // Set in local scope if exists, otherwise create in current local scope
+                let name = self.bytecode.name(name);
+                let scope = self.locals.last_mut().unwrap_or(&mut self.globals);
+                match scope.get_mut(name) {
+                    Some(slot) => *slot = val,
+                    None => {
+                        scope.insert(name.to_string(), val);
+                    }
                 }
-                OpCode::Jump(offset) => {
+            }
+            OpCode::Pop => {
+                self.stack.pop();
+            }
+            OpCode::Dup => {
+                let val = self.pop()?;
+                self.stack.push(val.clone());
+                self.stack.push(val);
+            }
+            OpCode::Swap => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            OpCode::Add => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.add_values(a, b)?;
+                self.stack.push(result);
+            }
+            OpCode::Sub => {
+                let (a, b) = self.pop_numbers()?;
+                self.stack.push(Value::Number(a - b));
+            }
+            OpCode::Mul => {
+                let (a, b) = self.pop_numbers()?;
+                self.stack.push(Value::Number(a * b));
+            }
+            OpCode::Div => {
+                let (a, b) = self.pop_numbers()?;
+                self.stack.push(Value::Number(a / b));
+            }
+            OpCode::Mod => {
+                let (a, b) = self.pop_numbers()?;
+                self.stack.push(Value::Number(a % b));
+            }
+            OpCode::Pow => {
+                let (a, b) = self.pop_numbers()?;
+                self.stack.push(Value::Number(a.powf(b)));
+            }
+            OpCode::Eq => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.values_equal(a, b)?;
+                self.stack.push(Value::Bool(result));
+            }
+            OpCode::Ne => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.values_equal(a, b)?;
+                self.stack.push(Value::Bool(!result));
+            }
+            OpCode::StrictEq => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push(Value::Bool(strict_equals(&a, &b)));
+            }
+            // a comparison with NaN is false, both ways
+            OpCode::Lt => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.less_than(a, b)?;
+                self.stack.push(Value::Bool(result == Some(true)));
+            }
+            OpCode::Gt => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.less_than(b, a)?;
+                self.stack.push(Value::Bool(result == Some(true)));
+            }
+            OpCode::Le => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.less_than(b, a)?;
+                self.stack.push(Value::Bool(result == Some(false)));
+            }
+            OpCode::Ge => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = self.less_than(a, b)?;
+                self.stack.push(Value::Bool(result == Some(false)));
+            }
+            OpCode::Neg => {
+                let a = self.pop()?;
+                let a = self.primitive_value(a, Hint::Number)?.to_number();
+                self.stack.push(Value::Number(-a));
+            }
+            OpCode::Not => {
+                let a = self.pop()?;
+                self.stack.push(Value::Bool(!a.is_truthy()));
+            }
+            OpCode::Jump(offset) => {
+                self.pc = (self.pc as i32 + offset - 1) as usize;
+            }
+            OpCode::JumpIf(offset) => {
+                let cond = self.pop()?;
+                if cond.is_truthy() {
                     self.pc = (self.pc as i32 + offset - 1) as usize;
                 }
-                OpCode::JumpIf(offset) => {
-                    let cond = self.stack.pop().unwrap();
-                    if cond.is_truthy() {
-                        self.pc = (self.pc as i32 + offset - 1) as usize;
-                    }
+            }
+            OpCode::JumpIfNot(offset) => {
+                let cond = self.pop()?;
+                if !cond.is_truthy() {
+                    self.pc = (self.pc as i32 + offset - 1) as usize;
                 }
-                OpCode::JumpIfNot(offset) => {
-                    let cond = self.stack.pop().unwrap();
-                    if !cond.is_truthy() {
-                        self.pc = (self.pc as i32 + offset - 1) as usize;
+            }
+            OpCode::FnStart {
+                name,
+                argc,
+                body_offset,
+            } => {
+                _ = body_offset;
+
+                // Store function definition, skip over body during normal execution
+                let addr = self.pc;
+                // Find FnEnd to skip body
+                let mut depth = 1;
+                let mut end_pos = self.pc;
+                while depth > 0 && end_pos < self.bytecode.instructions.len() {
+                    match self.bytecode.instructions[end_pos] {
+                        OpCode::FnStart { .. } => depth += 1,
+                        OpCode::FnEnd => depth -= 1,
+                        _ => {}
                     }
+                    end_pos += 1;
                 }
-                OpCode::FnStart {
-                    name,
+                // Store function reference
+                let name = self.bytecode.name(name).to_string();
+                let func = Value::Function {
+                    name: name.clone(),
+                    addr,
                     argc,
-                    body_offset,
-                } => {
-                    _ = body_offset;
-
-                    // Store function definition, skip over body during normal execution
-                    let addr = self.pc;
-                    // Find FnEnd to skip body
-                    let mut depth = 1;
-                    let mut end_pos = self.pc;
-                    while depth > 0 && end_pos < self.bytecode.instructions.len() {
-                        match self.bytecode.instructions[end_pos] {
-                            OpCode::FnStart { .. } => depth += 1,
-                            OpCode::FnEnd => depth -= 1,
-                            _ => {}
-                        }
-                        end_pos += 1;
-                    }
-                    // Store function reference
-                    let func = Value::Function {
-                        name: name.clone(),
-                        addr,
-                        argc,
-                    };
-                    self.globals.insert(name, func);
-                    // Skip to after FnEnd
-                    self.pc = end_pos;
-                }
-                OpCode::FnEnd => {
-                    // Marker, should be handled by FnStart or Return
-                }
-                OpCode::Call { name, argc } => {
-                    let func = self.get_variable(&name);
-                    self.call_function(func, argc);
-                }
-                OpCode::CallDynamic { argc } => {
-                    let func = self.stack.pop().unwrap();
-                    self.call_function(func, argc);
-                }
-                OpCode::Return { has_value } => {
-                    let val = if has_value {
-                        self.stack.pop().unwrap()
-                    } else {
-                        Value::Undefined
-                    };
-                    if let Some(ret_addr) = self.call_stack.pop() {
-                        self.locals.pop();
-                        self.pc = ret_addr;
-                        self.stack.push(val);
-                    } else {
-                        return val;
-                    }
+                };
+                self.globals.insert(name, func);
+                // Skip to after FnEnd
+                self.pc = end_pos;
+            }
+            OpCode::FnEnd => {
+                // Marker, should be handled by FnStart or Return
+            }
+            OpCode::Call { name, argc } => {
+                let args = self.pop_args(argc)?;
+                let name = self.bytecode.name(name);
+                let Some(func) = self.lookup(name) else {
+                    return Err(self.error(
+                        ErrorKind::ReferenceError,
+                        format!("{} is not defined", name),
+                    ));
+                };
+                if !func.is_callable() {
+                    return Err(
+                        self.error(ErrorKind::TypeError, format!("{} is not a function", name))
+                    );
                 }
-                OpCode::PushScope => {
-                    let val = self.stack.pop().unwrap();
-                    self.scope_chain.push(val);
+                self.call_function(func, Value::Undefined, args)?;
+            }
+            OpCode::CallDynamic { argc } => {
+                // the function is pushed before its arguments
+                let args = self.pop_args(argc)?;
+                let func = self.pop()?;
+                if !func.is_callable() {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        format!("{} is not a function", func.describe()),
+                    ));
                 }
-                OpCode::PopScope => {
-                    self.scope_chain.pop();
+                self.call_function(func, Value::Undefined, args)?;
+            }
+            OpCode::CallMethod { argc } => {
+                let args = self.pop_args(argc)?;
+                let func = self.pop()?;
+                let this = self.pop()?;
+                if !func.is_callable() {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        format!("{} is not a function", func.describe()),
+                    ));
                 }
-                OpCode::Kill(name) => {
-                    // Remove variable from current scope
-                    if let Some(locals) = self.locals.last_mut() {
-                        locals.remove(&name);
+                self.call_function(func, this, args)?;
+            }
+            OpCode::Return { has_value } => {
+                let val = if has_value {
+                    self.pop()?
+                } else {
+                    Value::Undefined
+                };
+                if let Some(frame) = self.call_stack.pop() {
+                    self.locals.pop();
+                    // handlers the function didn't remove
+                    while self
+                        .handlers
+                        .last()
+                        .is_some_and(|h| h.call_depth > self.call_stack.len())
+                    {
+                        self.handlers.pop();
                     }
-                    self.globals.remove(&name);
+                    self.pc = frame.return_addr;
+                    self.stack.push(val);
+                } else {
+                    return Ok(Some(val));
                 }
-                OpCode::MakeObject { pairs } => {
-                    let mut obj = std::collections::HashMap::new();
-                    for _ in 0..pairs {
-                        let val = self.stack.pop().unwrap();
-                        let key = self.stack.pop().unwrap().to_string();
-                        obj.insert(key, Box::new(val));
-                    }
-                    self.stack.push(Value::Object(Rc::new(RefCell::new(obj))));
+            }
+            OpCode::PushScope => {
+                let val = self.pop()?;
+                self.scope_chain.push(val);
+            }
+            OpCode::PopScope => {
+                self.scope_chain.pop();
+            }
+            OpCode::Kill(name) => {
+                // Remove variable from current scope
+                let name = self.bytecode.name(name);
+                if let Some(locals) = self.locals.last_mut() {
+                    locals.remove(name);
                 }
-                OpCode::MakeArray { len } => {
-                    let mut arr = Vec::new();
-                    for _ in 0..len {
-                        arr.push(self.stack.pop().unwrap());
-                    }
-                    arr.reverse();
-                    self.stack.push(Value::Array(arr));
+                self.globals.remove(name);
+            }
+            OpCode::MakeObject { pairs } => {
+                // the pairs are on the stack in source order
+                let Some(start) = self.stack.len().checked_sub(pairs as usize * 2) else {
+                    return Err(self.error(ErrorKind::InternalError, "stack underflow"));
+                };
+                let mut obj = Object::new();
+                let mut values = self.stack.split_off(start).into_iter();
+                while let (Some(key), Some(val)) = (values.next(), values.next()) {
+                    obj.insert(key.to_string(), val);
                 }
-                OpCode::GetProp => {
-                    let key = self.stack.pop().unwrap().to_string();
-                    let obj = self.stack.pop().unwrap();
-                    let val = self.get_property(obj, &key);
-                    self.stack.push(val);
+                self.stack.push(Value::Object(Rc::new(RefCell::new(obj))));
+            }
+            OpCode::MakeArray { len } => {
+                let mut arr = Vec::new();
+                for _ in 0..len {
+                    arr.push(self.pop()?);
                 }
-                OpCode::SetProp => {
-                    let val = self.stack.pop().unwrap();
-                    let key = self.stack.pop().unwrap().to_string();
-                    let obj = self.stack.pop().unwrap();
-                    self.set_property(obj, key, val);
+                arr.reverse();
+                self.stack.push(Value::Array(arr));
+            }
+            OpCode::GetProp => {
+                let key = self.pop_key()?;
+                let obj = self.pop()?;
+                if matches!(obj, Value::Null | Value::Undefined) {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        format!("cannot read property '{}' of {}", key, obj.describe()),
+                    ));
                 }
-                OpCode::HasProp => {
-                    let key = self.stack.pop().unwrap().to_string();
-                    let obj = self.stack.pop().unwrap();
-                    let has = self.has_property(&obj, &key);
-                    self.stack.push(Value::Bool(has));
+                let val = self.get_property(obj, &key);
+                self.stack.push(val);
+            }
+            OpCode::SetProp => {
+                let val = self.pop()?;
+                let key = self.pop_key()?;
+                let obj = self.pop()?;
+                if matches!(obj, Value::Null | Value::Undefined) {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        format!("cannot set property '{}' of {}", key, obj.describe()),
+                    ));
                 }
-                OpCode::ForInStart => {
-                    let obj = self.stack.pop().unwrap();
-                    let keys = self.get_object_keys(obj);
-                    self.stack
-                        .push(Value::String(format!("__iter_{}", self.iterators.len())));
-                    self.iterators
-                        .insert(format!("__iter_{}", self.iterators.len()), keys);
+                self.set_property(obj, key, val);
+            }
+            OpCode::HasProp => {
+                let key = self.pop_key()?;
+                let obj = self.pop()?;
+                if !matches!(obj, Value::Object(_) | Value::Array(_)) {
+                    return Err(self.error(
+                        ErrorKind::TypeError,
+                        format!(
+                            "cannot use 'in' to search for '{}' in {}",
+                            key,
+                            obj.describe()
+                        ),
+                    ));
                 }
-                OpCode::ForInNext => {
-                    let iter_key = self.stack.pop().unwrap().to_string();
-                    if let Some(keys) = self.iterators.get_mut(&iter_key) {
-                        if let Some(key) = keys.pop() {
-                            self.stack.push(Value::String(key));
-                        } else {
-                            self.stack.push(Value::Undefined);
-                        }
-                    } else {
-                        self.stack.push(Value::Undefined);
+                let has = self.has_property(&obj, &key);
+                self.stack.push(Value::Bool(has));
+            }
+            OpCode::Typeof => {
+                let val = self.pop()?;
+                self.stack.push(Value::String(val.type_of().to_string()));
+            }
+            OpCode::TypeofVar(name) => {
+                let name = self.bytecode.name(name);
+                let type_of = self.lookup(name).map_or("undefined", |val| val.type_of());
+                self.stack.push(Value::String(type_of.to_string()));
+            }
+            OpCode::Delete => {
+                let key = self.pop_key()?;
+                let obj = self.pop()?;
+                let deleted = match obj {
+                    Value::Object(object) => object.borrow_mut().delete(&key),
+                    Value::Null | Value::Undefined => {
+                        return Err(self.error(
+                            ErrorKind::TypeError,
+                            format!("cannot delete property '{}' of {}", key, obj.describe()),
+                        ));
                     }
-                }
-                OpCode::Nop => {}
-                OpCode::Halt => {
-                    return self.stack.pop().unwrap_or(Value::Undefined);
-                }
+                    _ => true,
+                };
+                self.stack.push(Value::Bool(deleted));
+            }
+            OpCode::DeleteVar(name) => {
+                let name = self.bytecode.name(name);
+                let deleted = self.delete_variable(name);
+                self.stack.push(Value::Bool(deleted));
+            }
+            OpCode::ForInStart => {
+                let obj = self.pop()?;
+                let iterator = ForInIterator::new(obj);
+                self.stack
+                    .push(Value::Iterator(Rc::new(RefCell::new(iterator))));
+            }
+            OpCode::ForInNext => {
+                let Value::Iterator(iterator) = self.pop()? else {
+                    return Err(self.error(
+                        ErrorKind::InternalError,
+                        "ForInNext on a value that is not an iterator",
+                    ));
+                };
+                let key = iterator.borrow_mut().next_key();
+                self.stack
+                    .push(key.map(Value::String).unwrap_or(Value::Undefined));
+            }
+            OpCode::Try(offset) => {
+                self.handlers.push(Handler {
+                    pc: (self.pc as i32 + offset - 1) as usize,
+                    stack_len: self.stack.len(),
+                    call_depth: self.call_stack.len(),
+                    locals_len: self.locals.len(),
+                    scope_len: self.scope_chain.len(),
+                });
+            }
+            OpCode::EndTry => {
+                self.handlers.pop();
+            }
+            OpCode::Throw => {
+                let val = self.pop()?;
+                return Err(self.throw(val));
+            }
+            OpCode::Nop => {}
+            OpCode::Halt => {
+                return Ok(Some(self.stack.pop().unwrap_or(Value::Undefined)));
             }
         }
+        Ok(None)
     }
 
-    fn const_to_value(&self, c: Const) -> Value {
+    fn const_to_value(&self, c: &Const) -> Value {
         use Const::*;
         match c {
-            Number(n) => Value::Number(n),
-            String(s) => Value::String(s),
-            Boolean(b) => Value::Bool(b),
+            Number(n) => Value::Number(*n),
+            String(s) => Value::String(s.clone()),
+            Boolean(b) => Value::Bool(*b),
             Undefined => Value::Undefined,
             Null => Value::Null,
         }
     }
 
+    /// Value of a variable, undefined if it is not declared
     pub fn get_variable(&self, name: &str) -> Value {
+        self.lookup(name).unwrap_or(Value::Undefined)
+    }
+
+    /// Define a global variable, for the host to give values to the script
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// Object the properties of numbers are looked up in, for the host to add
+    /// methods to numbers
+    pub fn number_prototype(&self) -> Rc<RefCell<Object>> {
+        self.number_prototype.clone()
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
         // Check locals first (innermost scope)
         for scope in self.locals.iter().rev() {
             if let Some(val) = scope.get(name) {
-                return val.clone();
+                return Some(val.clone());
             }
         }
         // Check globals
         if let Some(val) = self.globals.get(name) {
-            return val.clone();
+            return Some(val.clone());
         }
         // Check scope chain for with statements
         for scope in self.scope_chain.iter().rev() {
             if let Value::Object(obj) = scope {
-                if let Some(val) = obj.borrow().get(name) {
-                    return val.as_ref().clone();
+                if let Some(val) = obj.borrow().lookup(name) {
+                    return Some(val);
                 }
             }
         }
-        Value::Undefined
+        None
     }
 
-    fn set_variable(&mut self, name: String, val: Value) {
-        // Set in local scope if exists, otherwise create in current local scope
-        if let Some(locals) = self.locals.last_mut() {
-            locals.insert(name, val);
-        } else {
-            self.globals.insert(name, val);
+    // Same resolution as `lookup`: declared variables can't be deleted, a
+    // property of a with object can unless it is DontDelete, and deleting a
+    // name that resolves to nothing succeeds
+    fn delete_variable(&self, name: &str) -> bool {
+        if self.locals.iter().any(|scope| scope.contains_key(name))
+            || self.globals.contains_key(name)
+        {
+            return false;
+        }
+        for scope in self.scope_chain.iter().rev() {
+            if let Value::Object(obj) = scope
+                && obj.borrow().has_property(name)
+            {
+                return obj.borrow_mut().delete(name);
+            }
         }
+        true
     }
 
-    fn add_values(&self, a: Value, b: Value) -> Value {
-        // String concatenation if either is string
-        match (&a, &b) {
+    /// The `+` operator (ES1 section 11.6.1): string concatenation if either
+    /// side is a string once converted, addition otherwise
+    fn add_values(&mut self, a: Value, b: Value) -> Result<Value, RuntimeError> {
+        let a = self.primitive_value(a, Hint::Number)?;
+        let b = self.primitive_value(b, Hint::Number)?;
+        Ok(match (&a, &b) {
             (Value::String(sa), _) => Value::String(format!("{}{}", sa, b.to_string())),
             (_, Value::String(sb)) => Value::String(format!("{}{}", a.to_string(), sb)),
             _ => Value::Number(a.to_number() + b.to_number()),
+        })
+    }
+
+    // Operands of an arithmetic operator, converted to numbers from left to
+    // right
+    fn pop_numbers(&mut self) -> Result<(f64, f64), RuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let a = self.primitive_value(a, Hint::Number)?.to_number();
+        let b = self.primitive_value(b, Hint::Number)?.to_number();
+        Ok((a, b))
+    }
+
+    // Name of a property, an object key is converted by its toString first
+    fn pop_key(&mut self) -> Result<String, RuntimeError> {
+        let key = self.pop()?;
+        Ok(self.primitive_value(key, Hint::String)?.to_string())
+    }
+
+    /// ToPrimitive (ES1 section 9.1): an object is converted by its `valueOf`
+    /// and `toString` methods, in the order given by the hint ([[DefaultValue]],
+    /// ES1 section 8.6.2.6). The first one that returns a primitive gives the
+    /// result, it is a TypeError if none does
+    fn primitive_value(&mut self, value: Value, hint: Hint) -> Result<Value, RuntimeError> {
+        if !value.is_object() {
+            return Ok(value);
         }
+        let methods = match hint {
+            Hint::String => ["toString", "valueOf"],
+            Hint::Number => ["valueOf", "toString"],
+        };
+        for method in methods {
+            let func = self.get_property(value.clone(), method);
+            if !func.is_callable() {
+                // there is no Object.prototype yet, this is what its toString
+                // would return. Its valueOf returns the object itself
+                if method == "toString" {
+                    return Ok(Value::String(value.to_string()));
+                }
+                continue;
+            }
+            let result = self.call_value(func, value.clone(), Vec::new())?;
+            if !result.is_object() {
+                return Ok(result);
+            }
+        }
+        Err(self.error(
+            ErrorKind::TypeError,
+            "cannot convert object to primitive value",
+        ))
     }
 
-    fn values_equal(&self, a: Value, b: Value) -> bool {
+    /// Abstract equality, the `==` operator (ES1 section 11.9.3)
+    fn values_equal(&mut self, a: Value, b: Value) -> Result<bool, RuntimeError> {
         match (&a, &b) {
-            (Value::Number(na), Value::Number(nb)) => na == nb,
-            (Value::String(sa), Value::String(sb)) => sa == sb,
-            (Value::Bool(ba), Value::Bool(bb)) => ba == bb,
-            (Value::Null, Value::Null) => true,
-            (Value::Undefined, Value::Undefined) => true,
-            (Value::Null, Value::Undefined) => true,
-            (Value::Undefined, Value::Null) => true,
-            _ => false,
+            _ if std::mem::discriminant(&a) == std::mem::discriminant(&b) => {
+                Ok(strict_equals(&a, &b))
+            }
+            (Value::Null | Value::Undefined, Value::Null | Value::Undefined) => Ok(true),
+            (Value::Number(_), Value::String(_)) => {
+                Ok(strict_equals(&a, &Value::Number(b.to_number())))
+            }
+            (Value::String(_), Value::Number(_)) => {
+                Ok(strict_equals(&Value::Number(a.to_number()), &b))
+            }
+            (Value::Bool(_), _) => self.values_equal(Value::Number(a.to_number()), b),
+            (_, Value::Bool(_)) => self.values_equal(a, Value::Number(b.to_number())),
+            (Value::Number(_) | Value::String(_), _) if b.is_object() => {
+                let b = self.primitive_value(b, Hint::Number)?;
+                self.values_equal(a, b)
+            }
+            (_, Value::Number(_) | Value::String(_)) if a.is_object() => {
+                let a = self.primitive_value(a, Hint::Number)?;
+                self.values_equal(a, b)
+            }
+            _ => Ok(false),
         }
     }
 
-    fn call_function(&mut self, func: Value, argc: u8) {
+    /// Abstract relational comparison (ES1 section 11.8.5): whether `a < b`,
+    /// `None` when one side is NaN
+    fn less_than(&mut self, a: Value, b: Value) -> Result<Option<bool>, RuntimeError> {
+        let a = self.primitive_value(a, Hint::Number)?;
+        let b = self.primitive_value(b, Hint::Number)?;
+        if let (Value::String(a), Value::String(b)) = (&a, &b) {
+            // code unit by code unit, a prefix is smaller
+            return Ok(Some(a.encode_utf16().lt(b.encode_utf16())));
+        }
+        let (a, b) = (a.to_number(), b.to_number());
+        if a.is_nan() || b.is_nan() {
+            Ok(None)
+        } else {
+            Ok(Some(a < b))
+        }
+    }
+
+    // Arguments of a call, in order
+    fn pop_args(&mut self, argc: u8) -> Result<Vec<Value>, RuntimeError> {
+        match self.stack.len().checked_sub(argc as usize) {
+            Some(start) => Ok(self.stack.split_off(start)),
+            None => Err(self.error(ErrorKind::InternalError, "stack underflow")),
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        func: Value,
+        this: Value,
+        args: Vec<Value>,
+    ) -> Result<(), RuntimeError> {
         match func {
             Value::Function {
-                name: _,
+                name,
                 addr,
                 argc: expected,
             } => {
-                if argc != expected {
-                    // Type error, but we'll be lenient for now
-                }
-                // Pop arguments from stack
-                let mut args = Vec::new();
-                for _ in 0..argc {
-                    args.push(self.stack.pop().unwrap());
-                }
-                args.reverse();
-
-                // Setup call
-                self.call_stack.push(self.pc);
+                self.call_stack.push(CallFrame {
+                    return_addr: self.pc,
+                    function: name,
+                });
+                // Store args in locals, the function copies them to its
+                // parameters. Missing ones are undefined
                 let mut locals = std::collections::HashMap::new();
-                // Store args in locals (simplified - would use proper param names)
-                for (i, arg) in args.into_iter().enumerate() {
-                    locals.insert(format!("arg{}", i), arg);
+                let count = args.len().max(expected as usize);
+                let mut args = args.into_iter();
+                for i in 0..count {
+                    locals.insert(format!("arg{}", i), args.next().unwrap_or(Value::Undefined));
                 }
                 self.locals.push(locals);
                 self.pc = addr;
             }
-            Value::NativeFunction(f) => {
-                let mut args = Vec::new();
-                for _ in 0..argc {
-                    args.push(self.stack.pop().unwrap());
+            Value::NativeFunction(f) => match f(self, &args) {
+                Ok(result) => self.stack.push(result),
+                Err((kind, message)) => return Err(self.error(kind, message)),
+            },
+            Value::NativeMethod(f) => match f(self, &this, &args) {
+                Ok(result) => self.stack.push(result),
+                Err((kind, message)) => return Err(self.error(kind, message)),
+            },
+            _ => unreachable!("call of a value that is not callable"),
+        }
+        Ok(())
+    }
+
+    /// Call a function from inside an instruction and run it until it
+    /// returns. An exception it doesn't catch is given back to the
+    /// instruction, the handlers outside of the call are for the main loop
+    fn call_value(
+        &mut self,
+        func: Value,
+        this: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        // a valueOf that converts its own object would overflow the native
+        // stack
+        if self.nested_calls == MAX_NESTED_CALLS {
+            return Err(self.error(ErrorKind::RangeError, "Maximum call stack size exceeded"));
+        }
+        self.nested_calls += 1;
+        let result = self.run_call(func, this, args);
+        self.nested_calls -= 1;
+        result
+    }
+
+    fn run_call(
+        &mut self,
+        func: Value,
+        this: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let depth = self.call_stack.len();
+        // natives are done once called, script functions when their frame is
+        // popped
+        self.call_function(func, this, args)?;
+        while self.call_stack.len() > depth {
+            match self.step() {
+                Ok(None) => {}
+                Ok(Some(_)) => {
+                    return Err(self.error(
+                        ErrorKind::InternalError,
+                        "program halted inside a function call",
+                    ));
+                }
+                Err(error) => {
+                    if self.handlers.last().is_some_and(|h| h.call_depth > depth) {
+                        self.unwind(error)?;
+                    } else {
+                        return Err(error);
+                    }
                 }
-                args.reverse();
-                let result = f(self, &args);
-                self.stack.push(result.unwrap());
-            }
-            _ => {
-                // Not callable, push undefined
-                self.stack.push(Value::Undefined);
             }
         }
+        self.pop()
     }
 
-    fn get_property(&self, obj: Value, key: &str) -> Value {
+    fn get_property(&mut self, obj: Value, key: &str) -> Value {
         match obj {
-            Value::Object(map) => map
+            Value::Function { .. } if key == "prototype" => {
+                Value::Object(self.function_prototype(obj))
+            }
+            Value::Object(object) => object.borrow().lookup(key).unwrap_or(Value::Undefined),
+            Value::Number(_) => self
+                .number_prototype
                 .borrow()
-                .get(key)
-                .map(|v| v.as_ref().clone())
+                .lookup(key)
                 .unwrap_or(Value::Undefined),
             Value::Array(arr) => {
                 if let Ok(idx) = key.parse::<usize>() {
@@ -935,14 +1769,34 @@ impl VM {
     }
 
     fn set_property(&mut self, obj: Value, key: String, val: Value) {
-        if let Value::Object(map) = obj {
-            map.borrow_mut().insert(key, Box::new(val));
+        match (obj, val) {
+            (Value::Object(object), val) => object.borrow_mut().put(key, val),
+            (Value::Function { addr, .. }, Value::Object(prototype)) if key == "prototype" => {
+                self.prototypes.insert(addr, prototype);
+            }
+            _ => {}
         }
     }
 
+    /// The `prototype` of a script function, a new object has the function as
+    /// its DontEnum `constructor` (ES1 section 13)
+    fn function_prototype(&mut self, func: Value) -> Rc<RefCell<Object>> {
+        let Value::Function { addr, .. } = func else {
+            unreachable!("prototype of a value that is not a script function");
+        };
+        self.prototypes
+            .entry(addr)
+            .or_insert_with(|| {
+                let mut prototype = Object::new();
+                prototype.define("constructor".to_string(), func, Attributes::DONT_ENUM);
+                Rc::new(RefCell::new(prototype))
+            })
+            .clone()
+    }
+
     fn has_property(&self, obj: &Value, key: &str) -> bool {
         match obj {
-            Value::Object(map) => map.borrow().contains_key(key),
+            Value::Object(object) => object.borrow().has_property(key),
             Value::Array(arr) => {
                 if let Ok(idx) = key.parse::<usize>() {
                     idx < arr.len()
@@ -953,12 +1807,4 @@ impl VM {
             _ => false,
         }
     }
-
-    fn get_object_keys(&self, obj: Value) -> Vec<String> {
-        match obj {
-            Value::Object(map) => map.borrow().keys().cloned().collect(),
-            Value::Array(arr) => (0..arr.len()).map(|i| i.to_string()).collect(),
-            _ => Vec::new(),
-        }
-    }
 }
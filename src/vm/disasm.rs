@@ -0,0 +1,148 @@
+//! Human readable listing of the bytecode
+//!
+//! Every instruction is printed with its address, jumps show the absolute
+//! address they go to instead of their relative offset, and function bodies are
+//! indented between their `FnStart` and their end. When the bytecode carries
+//! debug info, the source line an instruction comes from is printed before the
+//! first instruction of that line:
+//!
+//! ```text
+//!       ; 1 | var i = 0; while (i < 3) i++;
+//! 0000  Const 0
+//! 0001  Store i
+//! 0002  Load i
+//! 0003  Const 3
+//! 0004  Lt
+//! 0005  JumpIfNot -> 0010
+//! ```
+
+use std::fmt::Write;
+
+use super::{Bytecode, OpCode};
+
+/// Text of the instruction at `pc`, with its constant and names resolved
+pub fn format_instruction(bytecode: &Bytecode, pc: usize) -> String {
+    let target = |offset: &i32| format!("{:04}", pc as i64 + *offset as i64);
+    let name = |index: &u32| match bytecode.names.get(*index as usize) {
+        Some(name) => name.clone(),
+        None => format!("<name #{}>", index),
+    };
+    match &bytecode.instructions[pc] {
+        OpCode::Const(index) => match bytecode.constants.get(*index as usize) {
+            Some(c) => format!("Const {}", c),
+            None => format!("Const <constant #{}>", index),
+        },
+        OpCode::Load(index) => format!("Load {}", name(index)),
+        OpCode::Store(index) => format!("Store {}", name(index)),
+        OpCode::Kill(index) => format!("Kill {}", name(index)),
+        OpCode::DeleteVar(index) => format!("DeleteVar {}", name(index)),
+        OpCode::TypeofVar(index) => format!("TypeofVar {}", name(index)),
+        OpCode::Jump(offset) => format!("Jump -> {}", target(offset)),
+        OpCode::JumpIf(offset) => format!("JumpIf -> {}", target(offset)),
+        OpCode::JumpIfNot(offset) => format!("JumpIfNot -> {}", target(offset)),
+        OpCode::Try(offset) => format!("Try -> {}", target(offset)),
+        OpCode::FnStart {
+            name: index,
+            argc,
+            body_offset,
+        } => format!(
+            "FnStart {}/{} (end {})",
+            name(index),
+            argc,
+            target(body_offset)
+        ),
+        OpCode::Call { name: index, argc } => format!("Call {}/{}", name(index), argc),
+        OpCode::CallDynamic { argc } => format!("CallDynamic /{}", argc),
+        OpCode::CallMethod { argc } => format!("CallMethod /{}", argc),
+        OpCode::Return { has_value: true } => "Return value".to_string(),
+        OpCode::Return { has_value: false } => "Return".to_string(),
+        OpCode::MakeObject { pairs } => format!("MakeObject {} pairs", pairs),
+        OpCode::MakeArray { len } => format!("MakeArray {}", len),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Listing of a whole program
+pub fn disassemble(bytecode: &Bytecode) -> String {
+    let mut out = String::new();
+    // functions we are in: (name, address of the end of the body)
+    let mut functions: Vec<(String, usize)> = Vec::new();
+    let mut last_line = None;
+
+    if let Some(debug) = &bytecode.debug
+        && !debug.file.is_empty()
+    {
+        writeln!(out, "; {}", debug.file).unwrap();
+    }
+
+    for (pc, op) in bytecode.instructions.iter().enumerate() {
+        while let Some((name, end)) = functions.last()
+            && *end <= pc
+        {
+            let indent = "  ".repeat(functions.len() - 1);
+            writeln!(out, "      {}; end of {}", indent, name).unwrap();
+            functions.pop();
+        }
+        let indent = "  ".repeat(functions.len());
+
+        if let Some(debug) = &bytecode.debug
+            && let Some((line, _)) = debug.position(pc)
+            && last_line != Some(line)
+        {
+            last_line = Some(line);
+            match debug.source_line(line) {
+                Some(text) => writeln!(out, "      {}; {} | {}", indent, line, text.trim()),
+                None => writeln!(out, "      {}; line {}", indent, line),
+            }
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "{:04}  {}{}",
+            pc,
+            indent,
+            format_instruction(bytecode, pc)
+        )
+        .unwrap();
+
+        match op {
+            OpCode::FnStart {
+                name, body_offset, ..
+            } => {
+                // a function without a known end lasts until its FnEnd
+                let end = if *body_offset > 0 {
+                    pc + *body_offset as usize
+                } else {
+                    usize::MAX
+                };
+                let name = bytecode.names.get(*name as usize).cloned();
+                functions.push((name.unwrap_or_default(), end));
+            }
+            OpCode::FnEnd => {
+                if let Some((name, _)) = functions.pop() {
+                    writeln!(
+                        out,
+                        "      {}; end of {}",
+                        "  ".repeat(functions.len()),
+                        name
+                    )
+                    .unwrap();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    while let Some((name, _)) = functions.pop() {
+        writeln!(
+            out,
+            "      {}; end of {}",
+            "  ".repeat(functions.len()),
+            name
+        )
+        .unwrap();
+    }
+
+    out
+}
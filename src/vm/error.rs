@@ -0,0 +1,125 @@
+//! Errors raised while running the bytecode
+//!
+//! A runtime error is a JavaScript value like any other: the VM builds an error
+//! object (`name`, `message` and `stack` properties) and raises it. When nothing
+//! handles it, it reaches the host as a `RuntimeError`, along with the call
+//! stack at the point it was raised:
+//!
+//! ```text
+//! Uncaught TypeError: g is not a function
+//!     at f (test.js:2:3)
+//!     at test.js:4:1
+//! ```
+
+use std::fmt;
+
+use super::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Error,
+    TypeError,
+    ReferenceError,
+    RangeError,
+    /// The bytecode itself is broken (stack underflow, jump out of the code...)
+    InternalError,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Error => "Error",
+            ErrorKind::TypeError => "TypeError",
+            ErrorKind::ReferenceError => "ReferenceError",
+            ErrorKind::RangeError => "RangeError",
+            ErrorKind::InternalError => "InternalError",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            ErrorKind::Error,
+            ErrorKind::TypeError,
+            ErrorKind::ReferenceError,
+            ErrorKind::RangeError,
+            ErrorKind::InternalError,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// Name of the function, `None` for the top-level code
+    pub function: Option<String>,
+    /// Address of the instruction being executed in this frame
+    pub pc: usize,
+    /// Its source position (line, column), if the bytecode has a line table
+    pub position: Option<(u32, u32)>,
+}
+
+/// Error that was raised and not handled by the script
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    /// The raised value, an error object for the errors raised by the VM
+    pub value: Value,
+    /// Call stack when it was raised, innermost frame first
+    pub stack: Vec<StackFrame>,
+    /// Source file of the program, empty if unknown
+    pub file: String,
+}
+
+impl RuntimeError {
+    fn property(&self, key: &str) -> Option<String> {
+        match &self.value {
+            Value::Object(object) => object.borrow().get(key).map(|v| v.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Kind of the error, `None` if the value isn't a known error object
+    pub fn kind(&self) -> Option<ErrorKind> {
+        ErrorKind::from_name(&self.property("name")?)
+    }
+
+    pub fn message(&self) -> String {
+        self.property("message")
+            .unwrap_or_else(|| self.value.to_string())
+    }
+
+    /// First line of the report: "TypeError: f is not a function"
+    pub fn summary(&self) -> String {
+        match (self.property("name"), self.property("message")) {
+            (Some(name), Some(message)) if message.is_empty() => name,
+            (Some(name), Some(message)) => format!("{}: {}", name, message),
+            _ => self.value.to_string(),
+        }
+    }
+
+    /// Stack trace in the format of the `stack` property of error objects
+    pub fn trace(&self) -> String {
+        let mut out = self.summary();
+        for frame in &self.stack {
+            out.push_str("\n    at ");
+            let location = match frame.position {
+                Some((line, column)) if self.file.is_empty() => format!("{}:{}", line, column),
+                Some((line, column)) => format!("{}:{}:{}", self.file, line, column),
+                None => format!("pc {:04}", frame.pc),
+            };
+            match &frame.function {
+                Some(name) => out.push_str(&format!("{} ({})", name, location)),
+                None => out.push_str(&location),
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Uncaught {}", self.trace())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,616 @@
+//! Binary encoding of the bytecode (`.tjbc` files)
+//!
+//! Layout of a file, all integers are little-endian and strings are stored as
+//! a u32 length followed by their UTF-8 bytes:
+//!
+//! ```text
+//! magic      "TJBC"
+//! version    u16
+//! flags      u16        bit 0: the debug section is present
+//! names      u32 count, then (string, u8 flags) per name
+//!            flags bit 0: the name is a compiler temporary
+//! constants  u32 count, then (u8 tag, payload) per constant
+//! functions  u32 count, then (u32 name, u8 argc, u32 pc) per function
+//! code       u32 count, then (u8 opcode, operands) per instruction
+//! debug      file and source strings,
+//!            u32 count, then (pc, line, column) per line entry
+//! checksum   u32        FNV-1a of everything before it
+//! ```
+//!
+//! The name table and the constant pool are written as they are in `Bytecode`,
+//! instructions keep their indices. `FnStart` refers to its entry in the
+//! function table, which records the address of every function so a loader
+//! doesn't have to scan the code.
+//!
+//! There is a line entry for most statements, so they are kept small: the pc is
+//! stored as the distance to the previous entry, the line as a signed
+//! difference with the previous line (zigzag encoded) and all three as LEB128
+//! varints. An entry usually fits in 3 bytes.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::{Bytecode, DebugInfo, LineEntry, OpCode};
+use crate::ir::Const;
+
+pub const MAGIC: &[u8; 4] = b"TJBC";
+/// The format hasn't been published yet, it changes along with the opcodes
+/// without a version bump
+pub const VERSION: u16 = 1;
+
+const FLAG_DEBUG: u16 = 1;
+
+const NAME_TEMPORARY: u8 = 1;
+
+const CONST_STRING: u8 = 0;
+const CONST_NUMBER: u8 = 1;
+const CONST_BOOLEAN: u8 = 2;
+const CONST_UNDEFINED: u8 = 3;
+const CONST_NULL: u8 = 4;
+
+/// Why a bytecode file was rejected
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file doesn't start with "TJBC"
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The file ends in the middle of a section
+    Truncated,
+    /// The content doesn't match the stored checksum
+    ChecksumMismatch,
+    /// The file is complete but describes an invalid program
+    Corrupted(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "cannot read bytecode: {}", err),
+            LoadError::BadMagic => write!(f, "not a TinyJS bytecode file"),
+            LoadError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported bytecode version {} (expected {})",
+                v, VERSION
+            ),
+            LoadError::Truncated => write!(f, "bytecode file is truncated"),
+            LoadError::ChecksumMismatch => write!(f, "bytecode checksum mismatch"),
+            LoadError::Corrupted(msg) => write!(f, "corrupted bytecode: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+impl Bytecode {
+    /// Serialize the program to `writer`
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Load a program written by `write_to`
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Bytecode, LoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        put_u16(&mut out, VERSION);
+        put_u16(&mut out, if self.debug.is_some() { FLAG_DEBUG } else { 0 });
+
+        put_u32(&mut out, self.names.len() as u32);
+        for (index, name) in self.names.iter().enumerate() {
+            put_str(&mut out, name);
+            out.push(if self.is_temporary(index as u32) {
+                NAME_TEMPORARY
+            } else {
+                0
+            });
+        }
+
+        put_u32(&mut out, self.constants.len() as u32);
+        for c in &self.constants {
+            match c {
+                Const::String(s) => {
+                    out.push(CONST_STRING);
+                    put_str(&mut out, s);
+                }
+                Const::Number(n) => {
+                    out.push(CONST_NUMBER);
+                    out.extend_from_slice(&n.to_bits().to_le_bytes());
+                }
+                Const::Boolean(b) => {
+                    out.push(CONST_BOOLEAN);
+                    out.push(*b as u8);
+                }
+                Const::Undefined => out.push(CONST_UNDEFINED),
+                Const::Null => out.push(CONST_NULL),
+            }
+        }
+
+        let functions: Vec<(usize, u32, u8)> = self
+            .instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(pc, op)| match op {
+                OpCode::FnStart { name, argc, .. } => Some((pc, *name, *argc)),
+                _ => None,
+            })
+            .collect();
+        put_u32(&mut out, functions.len() as u32);
+        for (pc, name, argc) in &functions {
+            put_u32(&mut out, *name);
+            out.push(*argc);
+            put_u32(&mut out, *pc as u32);
+        }
+
+        put_u32(&mut out, self.instructions.len() as u32);
+        let mut function_index = 0u32;
+        for op in &self.instructions {
+            encode_op(&mut out, op, &mut function_index);
+        }
+
+        if let Some(debug) = &self.debug {
+            put_str(&mut out, &debug.file);
+            put_str(&mut out, &debug.source);
+            put_u32(&mut out, debug.lines.len() as u32);
+            // the pcs are stored as deltas, in increasing order. `DebugInfo`
+            // asks for a sorted table but can't enforce it
+            let mut lines = debug.lines.clone();
+            lines.sort_by_key(|entry| entry.pc);
+            let (mut pc, mut line) = (0, 0);
+            for entry in &lines {
+                put_varint(&mut out, entry.pc - pc);
+                put_varint(&mut out, zigzag(entry.line as i32 - line as i32));
+                put_varint(&mut out, entry.column);
+                (pc, line) = (entry.pc, entry.line);
+            }
+        }
+
+        let sum = checksum(&out);
+        put_u32(&mut out, sum);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+        let mut r = Reader { bytes, pos: 0 };
+
+        if r.take(4).map_err(|_| LoadError::BadMagic)? != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let flags = r.u16()?;
+        if flags & !FLAG_DEBUG != 0 {
+            return Err(LoadError::Corrupted(format!("unknown flags {:#x}", flags)));
+        }
+
+        let mut names = Vec::new();
+        let mut temporaries = HashSet::new();
+        for index in 0..r.u32()? {
+            names.push(r.string()?);
+            match r.u8()? {
+                0 => {}
+                NAME_TEMPORARY => {
+                    temporaries.insert(index);
+                }
+                flags => {
+                    return Err(LoadError::Corrupted(format!(
+                        "unknown flags {:#x} of name {}",
+                        flags, index
+                    )));
+                }
+            }
+        }
+        let name = |index: u32| -> Result<u32, LoadError> {
+            if (index as usize) < names.len() {
+                Ok(index)
+            } else {
+                Err(LoadError::Corrupted(format!(
+                    "name index {} out of range",
+                    index
+                )))
+            }
+        };
+
+        let mut constants = Vec::new();
+        for _ in 0..r.u32()? {
+            let c = match r.u8()? {
+                CONST_STRING => Const::String(r.string()?),
+                CONST_NUMBER => Const::Number(f64::from_bits(r.u64()?)),
+                CONST_BOOLEAN => Const::Boolean(r.u8()? != 0),
+                CONST_UNDEFINED => Const::Undefined,
+                CONST_NULL => Const::Null,
+                tag => {
+                    return Err(LoadError::Corrupted(format!(
+                        "unknown constant tag {}",
+                        tag
+                    )));
+                }
+            };
+            constants.push(c);
+        }
+
+        let mut functions = Vec::new();
+        for _ in 0..r.u32()? {
+            functions.push((name(r.u32()?)?, r.u8()?, r.u32()?));
+        }
+
+        let count = r.u32()? as usize;
+        let mut instructions = Vec::new();
+        while instructions.len() < count {
+            let pc = instructions.len();
+            let op = match r.u8()? {
+                0 => {
+                    let index = r.u32()?;
+                    if index as usize >= constants.len() {
+                        return Err(LoadError::Corrupted(format!(
+                            "constant index {} out of range",
+                            index
+                        )));
+                    }
+                    OpCode::Const(index)
+                }
+                1 => OpCode::Load(name(r.u32()?)?),
+                2 => OpCode::Store(name(r.u32()?)?),
+                3 => OpCode::Pop,
+                4 => OpCode::Dup,
+                5 => OpCode::Swap,
+                6 => OpCode::Add,
+                7 => OpCode::Sub,
+                8 => OpCode::Mul,
+                9 => OpCode::Div,
+                10 => OpCode::Mod,
+                11 => OpCode::Pow,
+                12 => OpCode::Eq,
+                13 => OpCode::Ne,
+                14 => OpCode::Lt,
+                15 => OpCode::Gt,
+                16 => OpCode::Le,
+                17 => OpCode::Ge,
+                18 => OpCode::Neg,
+                19 => OpCode::Not,
+                20 => OpCode::Jump(r.i32()?),
+                21 => OpCode::JumpIf(r.i32()?),
+                22 => OpCode::JumpIfNot(r.i32()?),
+                23 => {
+                    let index = r.u32()?;
+                    let body_offset = r.i32()?;
+                    let Some(&(name, argc, at)) = functions.get(index as usize) else {
+                        return Err(LoadError::Corrupted(format!(
+                            "function index {} out of range",
+                            index
+                        )));
+                    };
+                    if at as usize != pc {
+                        return Err(LoadError::Corrupted(format!(
+                            "function '{}' is recorded at {} but starts at {}",
+                            names[name as usize], at, pc
+                        )));
+                    }
+                    OpCode::FnStart {
+                        name,
+                        argc,
+                        body_offset,
+                    }
+                }
+                24 => OpCode::FnEnd,
+                25 => OpCode::Call {
+                    name: name(r.u32()?)?,
+                    argc: r.u8()?,
+                },
+                26 => OpCode::CallDynamic { argc: r.u8()? },
+                27 => OpCode::Return {
+                    has_value: r.u8()? != 0,
+                },
+                28 => OpCode::PushScope,
+                29 => OpCode::PopScope,
+                30 => OpCode::Kill(name(r.u32()?)?),
+                31 => OpCode::MakeObject { pairs: r.u16()? },
+                32 => OpCode::MakeArray { len: r.u16()? },
+                33 => OpCode::GetProp,
+                34 => OpCode::SetProp,
+                35 => OpCode::HasProp,
+                36 => OpCode::ForInStart,
+                37 => OpCode::ForInNext,
+                38 => OpCode::Nop,
+                39 => OpCode::Halt,
+                40 => OpCode::Try(r.i32()?),
+                41 => OpCode::EndTry,
+                42 => OpCode::Throw,
+                43 => OpCode::Delete,
+                44 => OpCode::DeleteVar(name(r.u32()?)?),
+                45 => OpCode::Typeof,
+                46 => OpCode::TypeofVar(name(r.u32()?)?),
+                47 => OpCode::StrictEq,
+                48 => OpCode::CallMethod { argc: r.u8()? },
+                tag => {
+                    return Err(LoadError::Corrupted(format!(
+                        "unknown opcode {} at {}",
+                        tag, pc
+                    )));
+                }
+            };
+            instructions.push(op);
+        }
+
+        for (name, _, at) in &functions {
+            if !matches!(instructions.get(*at as usize), Some(OpCode::FnStart { name: n, .. }) if n == name)
+            {
+                return Err(LoadError::Corrupted(format!(
+                    "function '{}' has no FnStart at {}",
+                    names[*name as usize], at
+                )));
+            }
+        }
+
+        let debug = if flags & FLAG_DEBUG != 0 {
+            let file = r.string()?;
+            let source = r.string()?;
+            let mut lines: Vec<LineEntry> = Vec::new();
+            let (mut pc, mut line) = (0u32, 0u32);
+            for i in 0..r.u32()? {
+                let delta = r.varint()?;
+                if i > 0 && delta == 0 {
+                    return Err(LoadError::Corrupted("line table is not sorted".to_string()));
+                }
+                pc = pc
+                    .checked_add(delta)
+                    .filter(|pc| (*pc as usize) < instructions.len())
+                    .ok_or_else(|| {
+                        LoadError::Corrupted("line entry past the end of the code".to_string())
+                    })?;
+                line = line
+                    .checked_add_signed(unzigzag(r.varint()?))
+                    .ok_or_else(|| LoadError::Corrupted("invalid line number".to_string()))?;
+                lines.push(LineEntry {
+                    pc,
+                    line,
+                    column: r.varint()?,
+                });
+            }
+            Some(DebugInfo {
+                file,
+                source,
+                lines,
+            })
+        } else {
+            None
+        };
+
+        let end = r.pos;
+        let stored = r.u32()?;
+        if r.pos != bytes.len() {
+            return Err(LoadError::Corrupted(format!(
+                "{} trailing bytes",
+                bytes.len() - r.pos
+            )));
+        }
+        if checksum(&bytes[..end]) != stored {
+            return Err(LoadError::ChecksumMismatch);
+        }
+
+        Ok(Bytecode {
+            instructions,
+            constants,
+            names,
+            temporaries,
+            debug,
+        })
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+// LEB128: 7 bits per byte, the high bit is set on all bytes but the last
+fn put_varint(out: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn unzigzag(v: u32) -> i32 {
+    (v >> 1) as i32 ^ -((v & 1) as i32)
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn encode_op(out: &mut Vec<u8>, op: &OpCode, function_index: &mut u32) {
+    match op {
+        OpCode::Const(index) => {
+            out.push(0);
+            put_u32(out, *index);
+        }
+        OpCode::Load(name) => {
+            out.push(1);
+            put_u32(out, *name);
+        }
+        OpCode::Store(name) => {
+            out.push(2);
+            put_u32(out, *name);
+        }
+        OpCode::Pop => out.push(3),
+        OpCode::Dup => out.push(4),
+        OpCode::Swap => out.push(5),
+        OpCode::Add => out.push(6),
+        OpCode::Sub => out.push(7),
+        OpCode::Mul => out.push(8),
+        OpCode::Div => out.push(9),
+        OpCode::Mod => out.push(10),
+        OpCode::Pow => out.push(11),
+        OpCode::Eq => out.push(12),
+        OpCode::Ne => out.push(13),
+        OpCode::Lt => out.push(14),
+        OpCode::Gt => out.push(15),
+        OpCode::Le => out.push(16),
+        OpCode::Ge => out.push(17),
+        OpCode::Neg => out.push(18),
+        OpCode::Not => out.push(19),
+        OpCode::Jump(offset) => {
+            out.push(20);
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        OpCode::JumpIf(offset) => {
+            out.push(21);
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        OpCode::JumpIfNot(offset) => {
+            out.push(22);
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        OpCode::FnStart { body_offset, .. } => {
+            out.push(23);
+            put_u32(out, *function_index);
+            *function_index += 1;
+            out.extend_from_slice(&body_offset.to_le_bytes());
+        }
+        OpCode::FnEnd => out.push(24),
+        OpCode::Call { name, argc } => {
+            out.push(25);
+            put_u32(out, *name);
+            out.push(*argc);
+        }
+        OpCode::CallDynamic { argc } => {
+            out.push(26);
+            out.push(*argc);
+        }
+        OpCode::Return { has_value } => {
+            out.push(27);
+            out.push(*has_value as u8);
+        }
+        OpCode::PushScope => out.push(28),
+        OpCode::PopScope => out.push(29),
+        OpCode::Kill(name) => {
+            out.push(30);
+            put_u32(out, *name);
+        }
+        OpCode::MakeObject { pairs } => {
+            out.push(31);
+            put_u16(out, *pairs);
+        }
+        OpCode::MakeArray { len } => {
+            out.push(32);
+            put_u16(out, *len);
+        }
+        OpCode::GetProp => out.push(33),
+        OpCode::SetProp => out.push(34),
+        OpCode::HasProp => out.push(35),
+        OpCode::ForInStart => out.push(36),
+        OpCode::ForInNext => out.push(37),
+        OpCode::Nop => out.push(38),
+        OpCode::Halt => out.push(39),
+        OpCode::Try(offset) => {
+            out.push(40);
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        OpCode::EndTry => out.push(41),
+        OpCode::Throw => out.push(42),
+        OpCode::Delete => out.push(43),
+        OpCode::DeleteVar(name) => {
+            out.push(44);
+            put_u32(out, *name);
+        }
+        OpCode::Typeof => out.push(45),
+        OpCode::TypeofVar(name) => {
+            out.push(46);
+            put_u32(out, *name);
+        }
+        OpCode::StrictEq => out.push(47),
+        OpCode::CallMethod { argc } => {
+            out.push(48);
+            out.push(*argc);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.pos.checked_add(n).ok_or(LoadError::Truncated)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(LoadError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, LoadError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Result<u32, LoadError> {
+        let mut v = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            if shift == 28 && byte > 0x0f {
+                return Err(LoadError::Corrupted("varint is too large".to_string()));
+            }
+            v |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        unreachable!()
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()? as usize;
+        let s = std::str::from_utf8(self.take(len)?)
+            .map_err(|_| LoadError::Corrupted("string is not valid UTF-8".to_string()))?;
+        Ok(s.to_string())
+    }
+}
@@ -0,0 +1,220 @@
+//! Conversions between strings and numbers
+//!
+//! `string_to_number` is ToNumber applied to a string (ES1 section 9.3.1): the
+//! whole string, without its leading and trailing white space, must be a
+//! StringNumericLiteral, otherwise the result is NaN:
+//!
+//! ```text
+//! StringNumericLiteral  ::= StrWhiteSpace? (StrDecimalLiteral | HexIntegerLiteral)? StrWhiteSpace?
+//! StrDecimalLiteral     ::= ("+" | "-")? ("Infinity" | Digits "." Digits? Exponent?
+//!                           | "." Digits Exponent? | Digits Exponent?)
+//! HexIntegerLiteral     ::= ("0x" | "0X") HexDigits
+//! ```
+//!
+//! `parse_float` (ES1 section 15.1.2.3) reads the longest StrDecimalLiteral at
+//! the start of the string and ignores what follows it.
+//!
+//! The other way, `number_to_string` prints the shortest digits that convert
+//! back to the same number, like the other engines do.
+
+// StrWhiteSpaceChar: tab, vertical tab, form feed, space and line terminators
+fn is_white_space(c: char) -> bool {
+    matches!(c, '\t' | '\u{b}' | '\u{c}' | ' ' | '\n' | '\r')
+}
+
+fn count_digits(bytes: &[u8], from: usize) -> usize {
+    bytes[from..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+        .count()
+}
+
+// Longest StrDecimalLiteral at the start of `s`: its length and its value
+fn decimal_prefix(s: &str) -> Option<(usize, f64)> {
+    let bytes = s.as_bytes();
+    let negative = bytes.first() == Some(&b'-');
+    let mut i = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+
+    if s[i..].starts_with("Infinity") {
+        let value = if negative {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        };
+        return Some((i + "Infinity".len(), value));
+    }
+
+    let int_start = i;
+    let int_digits = count_digits(bytes, i);
+    i += int_digits;
+    let mut frac = "";
+    if bytes.get(i) == Some(&b'.') {
+        let frac_digits = count_digits(bytes, i + 1);
+        // a lone "." is not a number, "1." is
+        if int_digits == 0 && frac_digits == 0 {
+            return None;
+        }
+        frac = &s[i + 1..i + 1 + frac_digits];
+        i += 1 + frac_digits;
+    } else if int_digits == 0 {
+        return None;
+    }
+    let int = &s[int_start..int_start + int_digits];
+
+    // the exponent is only part of the literal if it has digits
+    let mut exponent = "0";
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(i + 1), Some(b'+' | b'-')));
+        let exp_digits = count_digits(bytes, i + 1 + sign);
+        if exp_digits > 0 {
+            exponent = &s[i + 1..i + 1 + sign + exp_digits];
+            i += 1 + sign + exp_digits;
+        }
+    }
+
+    // Rust rounds the decimal value correctly, as the spec asks
+    let literal = format!(
+        "{}{}.{}e{}",
+        if negative { "-" } else { "" },
+        if int.is_empty() { "0" } else { int },
+        if frac.is_empty() { "0" } else { frac },
+        exponent
+    );
+    Some((i, literal.parse().unwrap_or(f64::NAN)))
+}
+
+fn hex_integer(digits: &str) -> Option<f64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits
+        .chars()
+        .try_fold(0.0, |value, c| Some(value * 16.0 + c.to_digit(16)? as f64))
+}
+
+/// ToNumber of a string
+pub fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(is_white_space);
+    if s.is_empty() {
+        return 0.0;
+    }
+    if let Some(digits) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return hex_integer(digits).unwrap_or(f64::NAN);
+    }
+    match decimal_prefix(s) {
+        Some((len, value)) if len == s.len() => value,
+        _ => f64::NAN,
+    }
+}
+
+/// The global `parseFloat`
+pub fn parse_float(s: &str) -> f64 {
+    let s = s.trim_start_matches(is_white_space);
+    decimal_prefix(s).map_or(f64::NAN, |(_, value)| value)
+}
+
+/// ToString of a number (ES1 section 9.8.1): the shortest decimal digits that
+/// read back as the same number, in plain notation for exponents up to 21
+pub fn number_to_string(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value == 0.0 {
+        // -0 too
+        return "0".to_string();
+    }
+    if value < 0.0 {
+        return format!("-{}", number_to_string(-value));
+    }
+    if value.is_infinite() {
+        return "Infinity".to_string();
+    }
+
+    // Rust prints the shortest round-trip digits: "d.ddde[-]x"
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap() + 1;
+
+    if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n - 1 < 0 { '-' } else { '+' };
+        let (first, rest) = digits.split_at(1);
+        if rest.is_empty() {
+            format!("{}e{}{}", first, sign, (n - 1).abs())
+        } else {
+            format!("{}.{}e{}{}", first, rest, sign, (n - 1).abs())
+        }
+    }
+}
+
+/// Digits of a number in another base than 10, with as many fraction digits as
+/// needed to tell it apart from its neighbours
+pub fn number_to_radix_string(value: f64, radix: u32) -> String {
+    if radix == 10 || value.is_nan() || value.is_infinite() || value == 0.0 {
+        return number_to_string(value);
+    }
+    if value < 0.0 {
+        return format!("-{}", number_to_radix_string(-value, radix));
+    }
+    let base = radix as f64;
+    let digit_char = |digit: u32| char::from_digit(digit, radix).unwrap();
+
+    let mut integer = value.trunc();
+    let mut fraction = value - integer;
+    // half the distance to the next number, the fraction digits stop when
+    // they are that precise
+    let mut delta = (0.5 * (value.next_up() - value)).max(0.0_f64.next_up());
+    let mut fraction_digits: Vec<u32> = Vec::new();
+    if fraction >= delta {
+        loop {
+            fraction *= base;
+            delta *= base;
+            let digit = fraction as u32;
+            fraction_digits.push(digit);
+            fraction -= digit as f64;
+            // round half to even, carrying into the digits already written
+            if (fraction > 0.5 || (fraction == 0.5 && digit & 1 == 1)) && fraction + delta > 1.0 {
+                loop {
+                    match fraction_digits.pop() {
+                        Some(digit) if digit + 1 < radix => {
+                            fraction_digits.push(digit + 1);
+                            break;
+                        }
+                        Some(_) => {}
+                        None => {
+                            integer += 1.0;
+                            break;
+                        }
+                    }
+                }
+                break;
+            }
+            if fraction < delta {
+                break;
+            }
+        }
+    }
+
+    let mut integer_digits = Vec::new();
+    loop {
+        let digit = integer % base;
+        integer_digits.push(digit_char(digit as u32));
+        integer = (integer - digit) / base;
+        if integer < 1.0 {
+            break;
+        }
+    }
+    let mut out: String = integer_digits.iter().rev().collect();
+    if !fraction_digits.is_empty() {
+        out.push('.');
+        out.extend(fraction_digits.into_iter().map(digit_char));
+    }
+    out
+}
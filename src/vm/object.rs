@@ -0,0 +1,266 @@
+//! Objects of the heap
+//!
+//! An object is a set of named properties and an optional prototype, another
+//! object whose properties it inherits: reading a property that the object
+//! doesn't have looks it up along the prototype chain.
+//!
+//! Every property has the attributes of ES1 section 8.6.1: a ReadOnly property
+//! ignores the writes of the script (including through the objects inheriting
+//! it), a DontEnum one is skipped by `for-in` and a DontDelete one can't be
+//! deleted. The host is not bound by them: `insert` and `remove` always work.
+//!
+//! Properties are kept in the order they were added, with an index from their
+//! name for constant time lookups. Names that are array indices ("0", "1"...)
+//! come first, in increasing order, like in the other engines.
+//!
+//! `for-in` walks an object with a `ForInIterator`: the names to visit are
+//! collected when the loop starts (own properties first, then the inherited
+//! ones that are not shadowed), and a name whose property is deleted before
+//! its turn is skipped.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::Value;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Attributes {
+    pub read_only: bool,
+    pub dont_enum: bool,
+    pub dont_delete: bool,
+}
+
+impl Attributes {
+    /// Attributes of the constants of the built-in objects, like `Math.PI`
+    pub const CONSTANT: Attributes = Attributes {
+        read_only: true,
+        dont_enum: true,
+        dont_delete: true,
+    };
+    /// Attributes of the properties only hidden from `for-in`, like the
+    /// `stack` of error objects
+    pub const DONT_ENUM: Attributes = Attributes {
+        read_only: false,
+        dont_enum: true,
+        dont_delete: false,
+    };
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    value: Value,
+    attributes: Attributes,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    /// Properties in insertion order
+    properties: Vec<Property>,
+    /// Position of every property in `properties`
+    index: HashMap<String, usize>,
+    pub prototype: Option<Rc<RefCell<Object>>>,
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_prototype(prototype: Rc<RefCell<Object>>) -> Self {
+        Self {
+            prototype: Some(prototype),
+            ..Self::default()
+        }
+    }
+
+    /// Own property
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let index = *self.index.get(key)?;
+        Some(&self.properties[index].value)
+    }
+
+    /// Attributes of an own property
+    pub fn attributes(&self, key: &str) -> Option<Attributes> {
+        let index = *self.index.get(key)?;
+        Some(self.properties[index].attributes)
+    }
+
+    /// Set a property whatever its attributes, a new one goes after the
+    /// existing ones and has no attribute
+    pub fn insert(&mut self, key: String, value: Value) {
+        match self.index.get(&key) {
+            Some(&index) => self.properties[index].value = value,
+            None => self.define(key, value, Attributes::default()),
+        }
+    }
+
+    /// Set a property and its attributes
+    pub fn define(&mut self, key: String, value: Value, attributes: Attributes) {
+        match self.index.get(&key) {
+            Some(&index) => {
+                self.properties[index].value = value;
+                self.properties[index].attributes = attributes;
+            }
+            None => {
+                self.index.insert(key.clone(), self.properties.len());
+                self.properties.push(Property {
+                    name: key,
+                    value,
+                    attributes,
+                });
+            }
+        }
+    }
+
+    /// Remove a property whatever its attributes
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let index = self.index.remove(key)?;
+        let property = self.properties.remove(index);
+        for property in &self.properties[index..] {
+            *self.index.get_mut(&property.name).unwrap() -= 1;
+        }
+        Some(property.value)
+    }
+
+    /// Whether the script can set the property: it is not ReadOnly, on the
+    /// object or on the prototype it would be inherited from (ES1 [[CanPut]])
+    pub fn can_put(&self, key: &str) -> bool {
+        match self.attributes(key) {
+            Some(attributes) => !attributes.read_only,
+            None => self
+                .prototype
+                .as_ref()
+                .is_none_or(|prototype| prototype.borrow().can_put(key)),
+        }
+    }
+
+    /// Assignment by the script, ignored if the property can't be set (ES1
+    /// [[Put]])
+    pub fn put(&mut self, key: String, value: Value) {
+        if self.can_put(&key) {
+            self.insert(key, value);
+        }
+    }
+
+    /// Delete by the script, false if the property is DontDelete (ES1
+    /// [[Delete]])
+    pub fn delete(&mut self, key: &str) -> bool {
+        match self.attributes(key) {
+            Some(attributes) if attributes.dont_delete => false,
+            Some(_) => {
+                self.remove(key);
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Names of the own properties: array indices in increasing order, then
+    /// the other ones in insertion order
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        let mut indices: Vec<(u32, &String)> = self
+            .properties
+            .iter()
+            .filter_map(|p| array_index(&p.name).map(|index| (index, &p.name)))
+            .collect();
+        indices.sort_unstable_by_key(|(index, _)| *index);
+        let others = self
+            .properties
+            .iter()
+            .map(|p| &p.name)
+            .filter(|key| array_index(key).is_none());
+        indices.into_iter().map(|(_, key)| key).chain(others)
+    }
+
+    pub fn is_enumerable(&self, key: &str) -> bool {
+        self.attributes(key)
+            .is_some_and(|attributes| !attributes.dont_enum)
+    }
+
+    /// Property of the object or of its prototype chain
+    pub fn lookup(&self, key: &str) -> Option<Value> {
+        if let Some(value) = self.get(key) {
+            return Some(value.clone());
+        }
+        self.prototype.as_ref()?.borrow().lookup(key)
+    }
+
+    /// Whether the object or its prototype chain has the property
+    pub fn has_property(&self, key: &str) -> bool {
+        self.contains_key(key)
+            || self
+                .prototype
+                .as_ref()
+                .is_some_and(|prototype| prototype.borrow().has_property(key))
+    }
+}
+
+// The number a property name stands for when it is an array index: the
+// canonical form of an integer below 2^32 - 1
+fn array_index(key: &str) -> Option<u32> {
+    let index: u32 = key.parse().ok()?;
+    (index != u32::MAX && index.to_string() == key).then_some(index)
+}
+
+/// State of a `for-in` loop
+#[derive(Debug)]
+pub struct ForInIterator {
+    object: Value,
+    keys: Vec<String>,
+    next: usize,
+}
+
+impl ForInIterator {
+    pub fn new(object: Value) -> Self {
+        let keys = match &object {
+            Value::Object(object) => enumerable_keys(object),
+            Value::Array(arr) => (0..arr.len()).map(|i| i.to_string()).collect(),
+            _ => Vec::new(),
+        };
+        Self {
+            object,
+            keys,
+            next: 0,
+        }
+    }
+
+    /// Next name to visit, `None` once they have all been visited
+    pub fn next_key(&mut self) -> Option<String> {
+        while let Some(key) = self.keys.get(self.next) {
+            self.next += 1;
+            let present = match &self.object {
+                Value::Object(object) => object.borrow().has_property(key),
+                _ => true,
+            };
+            if present {
+                return Some(key.clone());
+            }
+        }
+        None
+    }
+}
+
+// Enumerable properties of the object then of its prototypes, a name shadowed
+// by a property closer in the chain is not visited again even if that property
+// is DontEnum
+fn enumerable_keys(object: &Rc<RefCell<Object>>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut keys = Vec::new();
+    let mut current = Some(object.clone());
+    while let Some(object) = current {
+        let object = object.borrow();
+        for key in object.keys() {
+            if seen.insert(key.clone()) && object.is_enumerable(key) {
+                keys.push(key.clone());
+            }
+        }
+        current = object.prototype.clone();
+    }
+    keys
+}
@@ -0,0 +1,270 @@
+//! Peephole optimisation of the bytecode
+//!
+//! The IR->bytecode compiler translates every IR instruction on its own, so the
+//! output is full of sequences like `Store t; Load t`, `Const; Not; JumpIf` or
+//! jumps to jumps. This pass rewrites small windows of instructions into cheaper
+//! ones, replacing what goes away with `Nop`, then removes every `Nop` and
+//! recomputes the relative jump offsets.
+//!
+//! A window never spans a jump target: the instructions of a pattern must always
+//! be executed together.
+
+use std::collections::HashSet;
+
+use super::{Bytecode, OpCode};
+use crate::ir::Const;
+
+/// Optimise the bytecode in place, returns the number of removed instructions
+pub fn optimize(bytecode: &mut Bytecode) -> usize {
+    let before = bytecode.instructions.len();
+
+    while rewrite(bytecode) {}
+    let new_index = remove_nops(&mut bytecode.instructions);
+
+    if let Some(debug) = &mut bytecode.debug {
+        for entry in &mut debug.lines {
+            entry.pc = new_index[entry.pc as usize] as u32;
+        }
+        // entries whose instructions all went away now share the pc of the
+        // next one, which is the only one still covering it
+        let lines = std::mem::take(&mut debug.lines);
+        for entry in lines {
+            if debug.lines.last().is_some_and(|last| last.pc == entry.pc) {
+                debug.lines.pop();
+            }
+            debug.lines.push(entry);
+        }
+        let end = bytecode.instructions.len() as u32;
+        debug.lines.retain(|entry| entry.pc < end);
+    }
+
+    before - bytecode.instructions.len()
+}
+
+fn const_truthiness(c: &Const) -> bool {
+    match c {
+        Const::String(s) => !s.is_empty(),
+        Const::Number(n) => *n != 0.0 && !n.is_nan(),
+        Const::Boolean(b) => *b,
+        Const::Undefined | Const::Null => false,
+    }
+}
+
+fn jump_offset(op: &OpCode) -> Option<i32> {
+    match op {
+        OpCode::Jump(o) | OpCode::JumpIf(o) | OpCode::JumpIfNot(o) | OpCode::Try(o) => Some(*o),
+        OpCode::FnStart { body_offset, .. } => Some(*body_offset),
+        _ => None,
+    }
+}
+
+fn with_offset(op: &OpCode, offset: i32) -> OpCode {
+    match *op {
+        OpCode::Jump(_) => OpCode::Jump(offset),
+        OpCode::JumpIf(_) => OpCode::JumpIf(offset),
+        OpCode::JumpIfNot(_) => OpCode::JumpIfNot(offset),
+        OpCode::Try(_) => OpCode::Try(offset),
+        OpCode::FnStart { name, argc, .. } => OpCode::FnStart {
+            name,
+            argc,
+            body_offset: offset,
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn jump_targets(code: &[OpCode]) -> HashSet<usize> {
+    code.iter()
+        .enumerate()
+        .filter_map(|(i, op)| jump_offset(op).map(|o| (i as i32 + o) as usize))
+        .collect()
+}
+
+// Next instruction that is not a Nop, starting at `i`
+fn skip_nops(code: &[OpCode], mut i: usize) -> usize {
+    while i < code.len() && code[i] == OpCode::Nop {
+        i += 1;
+    }
+    i
+}
+
+// One round of rewrites, returns true if something changed
+fn rewrite(bytecode: &mut Bytecode) -> bool {
+    let Bytecode {
+        instructions: code,
+        constants,
+        temporaries,
+        ..
+    } = bytecode;
+    let targets = jump_targets(code);
+    let loaded: HashSet<u32> = code
+        .iter()
+        .filter_map(|op| match op {
+            OpCode::Load(name) | OpCode::TypeofVar(name) => Some(*name),
+            _ => None,
+        })
+        .collect();
+    let mut changed = false;
+
+    for i in 0..code.len() {
+        if code[i] == OpCode::Nop {
+            continue;
+        }
+
+        // Jump threading: a jump to a jump goes directly to the final target
+        if let Some(offset) = jump_offset(&code[i])
+            && !matches!(code[i], OpCode::FnStart { .. })
+        {
+            let mut target = skip_nops(code, (i as i32 + offset) as usize);
+            let mut hops = 0;
+            while let Some(OpCode::Jump(next)) = code.get(target)
+                && hops < code.len()
+            {
+                let next_target = skip_nops(code, (target as i32 + next) as usize);
+                if next_target == target {
+                    break;
+                }
+                target = next_target;
+                hops += 1;
+            }
+            let new_offset = target as i32 - i as i32;
+            if matches!(code[i], OpCode::Jump(_)) && target == skip_nops(code, i + 1) {
+                code[i] = OpCode::Nop;
+                changed = true;
+                continue;
+            }
+            if new_offset != offset {
+                code[i] = with_offset(&code[i], new_offset);
+                changed = true;
+            }
+        }
+
+        // Store of a compiler temporary that is never loaded
+        if let OpCode::Store(name) = code[i]
+            && temporaries.contains(&name)
+            && !loaded.contains(&name)
+        {
+            code[i] = OpCode::Pop;
+            changed = true;
+        }
+
+        let j = skip_nops(code, i + 1);
+        if j >= code.len() || (i + 1..=j).any(|k| targets.contains(&k)) {
+            continue;
+        }
+
+        let replacement: Option<(OpCode, OpCode)> = match (code[i], code[j]) {
+            // Store x; Load x -> Dup; Store x
+            (OpCode::Store(a), OpCode::Load(b)) if a == b => Some((OpCode::Dup, OpCode::Store(a))),
+            // pushing a value to drop it right after
+            (OpCode::Const(_) | OpCode::Dup, OpCode::Pop) => Some((OpCode::Nop, OpCode::Nop)),
+            (OpCode::Const(c), OpCode::Not) => {
+                let negated = Const::Boolean(!const_truthiness(&constants[c as usize]));
+                let index = match constants.iter().position(|k| *k == negated) {
+                    Some(index) => index as u32,
+                    None => {
+                        constants.push(negated);
+                        constants.len() as u32 - 1
+                    }
+                };
+                Some((OpCode::Nop, OpCode::Const(index)))
+            }
+            (OpCode::Not, OpCode::JumpIf(o)) => Some((OpCode::Nop, OpCode::JumpIfNot(o))),
+            (OpCode::Not, OpCode::JumpIfNot(o)) => Some((OpCode::Nop, OpCode::JumpIf(o))),
+            (OpCode::Const(c), OpCode::JumpIf(o)) => {
+                Some(if const_truthiness(&constants[c as usize]) {
+                    (OpCode::Nop, OpCode::Jump(o))
+                } else {
+                    (OpCode::Nop, OpCode::Nop)
+                })
+            }
+            (OpCode::Const(c), OpCode::JumpIfNot(o)) => {
+                Some(if const_truthiness(&constants[c as usize]) {
+                    (OpCode::Nop, OpCode::Nop)
+                } else {
+                    (OpCode::Nop, OpCode::Jump(o))
+                })
+            }
+            _ => None,
+        };
+
+        if let Some((first, second)) = replacement {
+            code[i] = first;
+            code[j] = second;
+            changed = true;
+            continue;
+        }
+
+        // Store x overwritten by another Store x before being read
+        if let OpCode::Store(name) = code[i]
+            && is_dead_store(code, &targets, i, name)
+        {
+            code[i] = OpCode::Pop;
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+// Straight-line scan after a Store, over instructions that neither read the
+// variable nor can raise (with the stack holding what they pop, as in verified
+// code): an error between the two stores would stop the program, or reach a
+// handler, with the first value still in the variable
+fn is_dead_store(code: &[OpCode], targets: &HashSet<usize>, at: usize, name: u32) -> bool {
+    for (k, op) in code.iter().enumerate().skip(at + 1) {
+        if targets.contains(&k) {
+            return false;
+        }
+        match op {
+            OpCode::Store(other) if *other == name => return true,
+            OpCode::TypeofVar(other) if *other == name => return false,
+            OpCode::Const(_)
+            | OpCode::Store(_)
+            | OpCode::Pop
+            | OpCode::Dup
+            | OpCode::Swap
+            | OpCode::Not
+            | OpCode::Typeof
+            | OpCode::TypeofVar(_)
+            | OpCode::StrictEq
+            | OpCode::MakeObject { .. }
+            | OpCode::MakeArray { .. }
+            | OpCode::Nop => {}
+            // loads, calls, jumps, conversions of objects by their valueOf...
+            _ => return false,
+        }
+    }
+    false
+}
+
+// Drop the Nops and fix the relative offsets of the jumps, returns the new
+// position of every old one
+fn remove_nops(code: &mut Vec<OpCode>) -> Vec<usize> {
+    // new position of every old position (removed ones map to the next kept one)
+    let mut new_index = vec![0usize; code.len() + 1];
+    let mut kept = 0;
+    for (i, op) in code.iter().enumerate() {
+        new_index[i] = kept;
+        if *op != OpCode::Nop {
+            kept += 1;
+        }
+    }
+    new_index[code.len()] = kept;
+
+    let old = std::mem::take(code);
+    for (i, op) in old.iter().enumerate() {
+        if *op == OpCode::Nop {
+            continue;
+        }
+        let op = match jump_offset(op) {
+            Some(offset) => {
+                let target = ((i as i32 + offset).max(0) as usize).min(old.len());
+                with_offset(op, new_index[target] as i32 - new_index[i] as i32)
+            }
+            None => *op,
+        };
+        code.push(op);
+    }
+    new_index
+}
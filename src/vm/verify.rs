@@ -0,0 +1,349 @@
+//! Static checks on the bytecode before running it
+//!
+//! The VM trusts its input: it unwraps every pop and follows any jump. Bytecode
+//! that went through `verify` without errors can't make it index out of its
+//! instructions or pools, or pop from an empty stack:
+//!  - constants and names refer to existing entries of the pools
+//!  - jumps land inside the code, and inside the function they belong to
+//!  - execution never runs past the last instruction or the end of a function
+//!  - `FnStart` bodies are in range, correctly nested and end at the `FnEnd`
+//!    the VM skips them to
+//!  - no instruction pops more values than the stack holds on any path to it
+//!
+//! The compiler stores every value it pushes, so all the paths to an instruction
+//! reach it with the same stack depth. Bytecode from elsewhere may not, then the
+//! smallest depth is kept.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use super::disasm::format_instruction;
+use super::{Bytecode, OpCode};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub pc: usize,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: {}", self.pc, self.message)
+    }
+}
+
+// (values popped, values pushed)
+fn stack_effect(op: &OpCode) -> (usize, usize) {
+    match op {
+        OpCode::Const(_) | OpCode::Load(_) => (0, 1),
+        OpCode::Store(_) | OpCode::Pop => (1, 0),
+        OpCode::Dup => (1, 2),
+        OpCode::Swap => (2, 2),
+        OpCode::Add
+        | OpCode::Sub
+        | OpCode::Mul
+        | OpCode::Div
+        | OpCode::Mod
+        | OpCode::Pow
+        | OpCode::Eq
+        | OpCode::Ne
+        | OpCode::StrictEq
+        | OpCode::Lt
+        | OpCode::Gt
+        | OpCode::Le
+        | OpCode::Ge => (2, 1),
+        OpCode::Neg | OpCode::Not | OpCode::Typeof => (1, 1),
+        OpCode::JumpIf(_) | OpCode::JumpIfNot(_) | OpCode::Throw => (1, 0),
+        OpCode::Call { argc, .. } => (*argc as usize, 1),
+        OpCode::CallDynamic { argc } => (*argc as usize + 1, 1),
+        OpCode::CallMethod { argc } => (*argc as usize + 2, 1),
+        OpCode::Return { has_value } => (*has_value as usize, 0),
+        OpCode::PushScope => (1, 0),
+        OpCode::MakeObject { pairs } => (*pairs as usize * 2, 1),
+        OpCode::MakeArray { len } => (*len as usize, 1),
+        OpCode::GetProp | OpCode::HasProp | OpCode::Delete => (2, 1),
+        OpCode::DeleteVar(_) | OpCode::TypeofVar(_) => (0, 1),
+        OpCode::SetProp => (3, 0),
+        OpCode::ForInStart | OpCode::ForInNext => (1, 1),
+        OpCode::Jump(_)
+        | OpCode::Try(_)
+        | OpCode::EndTry
+        | OpCode::FnStart { .. }
+        | OpCode::FnEnd
+        | OpCode::PopScope
+        | OpCode::Kill(_)
+        | OpCode::Nop
+        | OpCode::Halt => (0, 0),
+    }
+}
+
+fn jump_target(pc: usize, op: &OpCode) -> Option<i64> {
+    match op {
+        OpCode::Jump(o) | OpCode::JumpIf(o) | OpCode::JumpIfNot(o) | OpCode::Try(o) => {
+            Some(pc as i64 + *o as i64)
+        }
+        _ => None,
+    }
+}
+
+pub fn verify(bytecode: &Bytecode) -> Vec<VerifyError> {
+    let code = &bytecode.instructions;
+    let mut errors = Vec::new();
+
+    if code.is_empty() {
+        errors.push(VerifyError {
+            pc: 0,
+            message: "empty program".to_string(),
+        });
+        return errors;
+    }
+
+    for (pc, op) in code.iter().enumerate() {
+        match op {
+            OpCode::Const(c) if *c as usize >= bytecode.constants.len() => {
+                errors.push(VerifyError {
+                    pc,
+                    message: format!("constant index {} is out of range", c),
+                });
+            }
+            OpCode::Load(name)
+            | OpCode::Store(name)
+            | OpCode::Kill(name)
+            | OpCode::DeleteVar(name)
+            | OpCode::TypeofVar(name)
+            | OpCode::Call { name, .. }
+            | OpCode::FnStart { name, .. }
+                if *name as usize >= bytecode.names.len() =>
+            {
+                errors.push(VerifyError {
+                    pc,
+                    message: format!("name index {} is out of range", name),
+                });
+            }
+            _ => {}
+        }
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    let Some(regions) = function_regions(bytecode, &mut errors) else {
+        return errors;
+    };
+
+    for (pc, op) in code.iter().enumerate() {
+        if let Some(target) = jump_target(pc, op) {
+            if target < 0 || target >= code.len() as i64 {
+                errors.push(VerifyError {
+                    pc,
+                    message: format!("jump target {} is out of range", target),
+                });
+            } else if regions.owner[target as usize] != regions.owner[pc] {
+                errors.push(VerifyError {
+                    pc,
+                    message: format!("jump to {:04} crosses a function boundary", target),
+                });
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    // entry of the top-level code and of every function body
+    let mut entries = vec![0];
+    entries.extend(
+        regions
+            .functions
+            .iter()
+            .filter(|(start, end)| start + 1 < *end)
+            .map(|(start, _)| start + 1),
+    );
+
+    let mut depth: Vec<Option<usize>> = vec![None; code.len()];
+    for entry in entries {
+        check_flow(bytecode, &regions, entry, &mut depth, &mut errors);
+    }
+
+    let mut seen = HashSet::new();
+    errors.retain(|e| seen.insert((e.pc, e.message.clone())));
+    errors.sort_by_key(|e| e.pc);
+    errors
+}
+
+// FnEnd closing the function declared at `pc`, found like the VM does when it
+// skips the body: the first one that doesn't close a nested function
+fn matching_fn_end(code: &[OpCode], pc: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (k, op) in code.iter().enumerate().skip(pc + 1) {
+        match op {
+            OpCode::FnStart { .. } => depth += 1,
+            OpCode::FnEnd if depth == 0 => return Some(k),
+            OpCode::FnEnd => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+struct Regions {
+    // (FnStart, end of the body) of every function
+    functions: Vec<(usize, usize)>,
+    // innermost function containing every instruction (index in `functions`)
+    owner: Vec<Option<usize>>,
+}
+
+fn function_regions(bytecode: &Bytecode, errors: &mut Vec<VerifyError>) -> Option<Regions> {
+    let code = &bytecode.instructions;
+    let mut functions: Vec<(usize, usize)> = Vec::new();
+    let mut owner = Vec::with_capacity(code.len());
+    let mut open: Vec<usize> = Vec::new();
+
+    for (pc, op) in code.iter().enumerate() {
+        while let Some(&f) = open.last()
+            && functions[f].1 <= pc
+        {
+            open.pop();
+        }
+        owner.push(open.last().copied());
+
+        if let OpCode::FnStart {
+            name, body_offset, ..
+        } = op
+        {
+            let end = pc as i64 + *body_offset as i64;
+            if *body_offset < 1 || end > code.len() as i64 {
+                errors.push(VerifyError {
+                    pc,
+                    message: format!(
+                        "body of function '{}' is out of range",
+                        bytecode.name(*name)
+                    ),
+                });
+                return None;
+            }
+            let end = end as usize;
+            if let Some(&outer) = open.last()
+                && end > functions[outer].1
+            {
+                errors.push(VerifyError {
+                    pc,
+                    message: format!(
+                        "function '{}' ends after its enclosing function",
+                        bytecode.name(*name)
+                    ),
+                });
+                return None;
+            }
+            functions.push((pc, end));
+            open.push(functions.len() - 1);
+        }
+    }
+
+    Some(Regions { functions, owner })
+}
+
+// Walk every path from `entry` and record the smallest stack depth of each
+// instruction
+fn check_flow(
+    bytecode: &Bytecode,
+    regions: &Regions,
+    entry: usize,
+    depth: &mut [Option<usize>],
+    errors: &mut Vec<VerifyError>,
+) {
+    let code = &bytecode.instructions;
+    let function = regions.owner[entry];
+    let end = match function {
+        Some(f) => regions.functions[f].1,
+        None => code.len(),
+    };
+    depth[entry] = Some(0);
+    let mut worklist = vec![entry];
+
+    while let Some(pc) = worklist.pop() {
+        let op = &code[pc];
+        let current = depth[pc].unwrap();
+        let (pops, pushes) = stack_effect(op);
+        if pops > current {
+            errors.push(VerifyError {
+                pc,
+                message: format!(
+                    "stack underflow: {} needs {} values but the stack may hold {}",
+                    format_instruction(bytecode, pc),
+                    pops,
+                    current
+                ),
+            });
+            continue;
+        }
+        let after = current - pops + pushes;
+
+        // (next instruction, stack depth there)
+        let mut successors = Vec::new();
+        match op {
+            OpCode::Return { .. } | OpCode::Throw | OpCode::Halt => {}
+            OpCode::Jump(_) => successors.push((jump_target(pc, op).unwrap() as usize, after)),
+            OpCode::JumpIf(_) | OpCode::JumpIfNot(_) => {
+                successors.push((jump_target(pc, op).unwrap() as usize, after));
+                successors.push((pc + 1, after));
+            }
+            OpCode::Try(_) => {
+                // the handler starts with the exception pushed on the stack
+                // restored to its depth at the Try
+                successors.push((jump_target(pc, op).unwrap() as usize, after + 1));
+                successors.push((pc + 1, after));
+            }
+            OpCode::FnStart {
+                name, body_offset, ..
+            } => {
+                // the VM skips the body by looking for the matching FnEnd
+                let body_end = pc + *body_offset as usize;
+                if code[body_end - 1] != OpCode::FnEnd {
+                    errors.push(VerifyError {
+                        pc,
+                        message: format!(
+                            "function '{}' is declared here but its body doesn't end with FnEnd",
+                            bytecode.name(*name)
+                        ),
+                    });
+                    continue;
+                }
+                if matching_fn_end(code, pc) != Some(body_end - 1) {
+                    errors.push(VerifyError {
+                        pc,
+                        message: format!(
+                            "body of function '{}' ends at {:04} but the VM skips it to another FnEnd",
+                            bytecode.name(*name),
+                            body_end - 1
+                        ),
+                    });
+                    continue;
+                }
+                successors.push((body_end, after));
+            }
+            _ => successors.push((pc + 1, after)),
+        }
+
+        for (next, after) in successors {
+            if next >= end {
+                let message = match function {
+                    Some(f) => match &code[regions.functions[f].0] {
+                        OpCode::FnStart { name, .. } => format!(
+                            "execution runs past the end of function '{}'",
+                            bytecode.name(*name)
+                        ),
+                        _ => unreachable!(),
+                    },
+                    None => "execution runs past the end of the code".to_string(),
+                };
+                errors.push(VerifyError { pc, message });
+                continue;
+            }
+            if depth[next].is_none_or(|d| after < d) {
+                depth[next] = Some(after);
+                worklist.push(next);
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Setup shared by the integration tests: source -> AST -> IR -> bytecode -> VM
+//!
+//! Every test crate uses a part of it only.
+#![allow(dead_code)]
+
+use tinyjs::ast;
+use tinyjs::ir;
+use tinyjs::vm;
+
+pub fn parse_program(source: &str) -> ast::Program {
+    tinyjs::parse_source(source)
+}
+
+pub fn compile_ir(source: &str) -> ir::Program {
+    tinyjs::compile_ast(parse_program(source))
+}
+
+pub fn compile(source: &str) -> vm::Bytecode {
+    tinyjs::compile_source(source)
+}
+
+pub fn machine(source: &str) -> vm::VM {
+    vm::VM::new(compile(source))
+}
+
+/// Value of `log` once the machine ran its program
+pub fn log_of(mut machine: vm::VM) -> String {
+    if let Err(err) = machine.run() {
+        panic!("{}", err);
+    }
+    format!("{:?}", machine.get_variable("log"))
+}
+
+/// Value of `log` once the program ran
+pub fn run_log(source: &str) -> String {
+    log_of(machine(source))
+}
@@ -1,28 +1,15 @@
 use tinyjs::ast;
-use tinyjs::lexer;
-use tinyjs::parser;
-
-fn parse_program(source: &str) -> ast::Program {
-    let mut lex = lexer::Lexer {
-        source: source.to_string(),
-        cursor: lexer::Cursor { row: 0, line: 0 },
-        line: 0,
-        row: 0,
-        prev_cr: false,
-    };
-    let tokens = lex.walk();
-    let mut parser = parser::Parser {
-        tokens: Vec::new(),
-        pos: 0,
-        allow_in: true,
-        source: source.to_string(),
-    };
-    parser.parse(tokens)
-}
+
+mod common;
+
+use common::parse_program;
 
 fn first_stmt(source: &str) -> ast::Stmt {
     let program = parse_program(source);
-    program.body.into_iter().next().expect("missing stmt")
+    match program.body.into_iter().next().expect("missing stmt") {
+        ast::Stmt::Spanned(_, stmt) => *stmt,
+        other => other,
+    }
 }
 
 fn expect_stmt(source: &str, label: &str, check: impl FnOnce(&ast::Stmt) -> bool) {
@@ -82,6 +69,25 @@ fn parses_while_statement() {
     });
 }
 
+#[test]
+fn parses_do_while_statement() {
+    expect_stmt("do { i++; } while (i < 3);", "do while statement", |stmt| {
+        matches!(stmt, ast::Stmt::DoWhile { body, .. }
+            if matches!(body.as_ref(), ast::Stmt::Spanned(_, b)
+                if matches!(b.as_ref(), ast::Stmt::Block(v) if v.len() == 1)))
+    });
+}
+
+#[test]
+fn parses_do_while_without_semicolon() {
+    let program = parse_program("do i++; while (i < 3) j++;");
+    assert_eq!(program.body.len(), 2);
+    match &program.body[1] {
+        ast::Stmt::Spanned(_, stmt) => assert!(matches!(stmt.as_ref(), ast::Stmt::Expr(_))),
+        other => panic!("expected the statement after the loop, got {:?}", other),
+    }
+}
+
 #[test]
 fn parses_for_statement() {
     expect_stmt(
@@ -110,14 +116,14 @@ fn parses_for_in_statement() {
 #[test]
 fn parses_continue_statement() {
     expect_stmt("continue;", "continue statement", |stmt| {
-        matches!(stmt, ast::Stmt::Continue)
+        matches!(stmt, ast::Stmt::Continue(None))
     });
 }
 
 #[test]
 fn parses_break_statement() {
     expect_stmt("break;", "break statement", |stmt| {
-        matches!(stmt, ast::Stmt::Break)
+        matches!(stmt, ast::Stmt::Break(None))
     });
 }
 
@@ -285,7 +291,7 @@ fn parses_nested_statement_block() {
 
 #[test]
 fn parses_asi_after_break() {
-    expect_stmt("break\n;", "break ASI", |stmt| matches!(stmt, ast::Stmt::Break));
+    expect_stmt("break\n;", "break ASI", |stmt| matches!(stmt, ast::Stmt::Break(None)));
 }
 
 #[test]
@@ -303,3 +309,38 @@ fn parses_object() {
         |stmt| matches!(stmt, ast::Stmt::Var(_)),
     );
 }
+
+#[test]
+fn parses_switch_statement() {
+    expect_stmt(
+        "switch (x) { case 1: a; case 2: default: b; break; }",
+        "switch statement",
+        |stmt| {
+            matches!(stmt, ast::Stmt::Switch { cases, .. }
+                if cases.len() == 3
+                    && cases[1].body.is_empty()
+                    && cases[2].test.is_none()
+                    && cases[2].body.len() == 2)
+        },
+    );
+}
+
+#[test]
+fn parses_labeled_statement() {
+    expect_stmt("outer: while (true) break outer;", "labeled statement", |stmt| {
+        matches!(stmt, ast::Stmt::Labeled(label, _) if label == "outer")
+    });
+    expect_stmt("continue outer;", "labeled continue", |stmt| {
+        matches!(stmt, ast::Stmt::Continue(Some(label)) if label == "outer")
+    });
+}
+
+#[test]
+fn parses_label_on_the_next_line_as_a_new_statement() {
+    let program = parse_program("break\nouter;");
+    assert_eq!(program.body.len(), 2);
+    assert!(matches!(
+        &program.body[0],
+        ast::Stmt::Spanned(_, stmt) if matches!(stmt.as_ref(), ast::Stmt::Break(None))
+    ));
+}
@@ -1,42 +1,11 @@
 // TODO: Update the tests to align on the AST compiler
 
-use tinyjs::ast;
 use tinyjs::ir;
-use tinyjs::lexer;
-use tinyjs::parser;
-
-fn parse_program(source: &str) -> ast::Program {
-    let mut lex = lexer::Lexer {
-        source: source.to_string(),
-        cursor: lexer::Cursor { row: 0, line: 0 },
-        line: 0,
-        row: 0,
-        prev_cr: false,
-    };
-
-    let tokens = lex.walk();
-    let mut parser = parser::Parser {
-        tokens: Vec::new(),
-        pos: 0,
-        allow_in: true,
-        source: source.to_string(),
-    };
-
-    parser.parse(tokens)
-}
+
+mod common;
 
 fn compile_ir(source: &str) -> Vec<ir::Instruction> {
-    let mut compiler = ir::Compiler {
-        source: parse_program(source),
-        pos: 0,
-        output: ir::Program { body: vec![] },
-        label_stack: 0,
-        loop_stack: vec![],
-        return_stack: vec![],
-    };
-
-    compiler.compile();
-    compiler.output.body
+    common::compile_ir(source).body
 }
 
 #[test]
@@ -45,6 +14,12 @@ fn translates_basic_var_declaration() {
 
     assert!(matches!(
         out.first(),
+        Some(ir::Instruction::Call {
+            function: ir::SoloFunction::Position(1, 1)
+        })
+    ));
+    assert!(matches!(
+        out.get(1),
         Some(ir::Instruction::Assign {
             dest,
             src: ir::Operand::Const(ir::Const::Number(1.0))
@@ -319,10 +294,13 @@ fn translates_object_member_assignment_shape() {
     assert!(out.iter().any(|instr| {
         matches!(
             instr,
-            ir::Instruction::Assign {
-                dest,
-                src: ir::Operand::Const(ir::Const::Number(1.0))
-            } if dest == "obj_a"
+            ir::Instruction::Call {
+                function: ir::SoloFunction::SetProp(
+                    ir::Operand::Var(obj),
+                    ir::Operand::Const(ir::Const::String(key)),
+                    ir::Operand::Const(ir::Const::Number(1.0))
+                )
+            } if obj == "obj" && key == "a"
         )
     }));
 }
@@ -334,35 +312,49 @@ fn translates_dynamic_index_assignment_shape() {
     assert!(out.iter().any(|instr| {
         matches!(
             instr,
-            ir::Instruction::Assign {
-                dest,
-                src: ir::Operand::Var(src)
-            } if dest == "obj_k" && src == "v"
+            ir::Instruction::Call {
+                function: ir::SoloFunction::SetProp(
+                    ir::Operand::Var(obj),
+                    ir::Operand::Var(key),
+                    ir::Operand::Var(value)
+                )
+            } if obj == "obj" && key == "k" && value == "v"
         )
     }));
 }
 
 #[test]
-fn translates_delete_member_to_undefined_and_true() {
-    let out = compile_ir("var ok = delete obj.a;");
+fn translates_delete_to_property_removal() {
+    let out = compile_ir("var ok = delete obj.a; delete obj[k]; delete x;");
 
     assert!(out.iter().any(|instr| {
         matches!(
             instr,
-            ir::Instruction::Assign {
-                dest,
-                src: ir::Operand::Const(ir::Const::Undefined)
-            } if dest == "obj_a"
+            ir::Instruction::Call {
+                function: ir::SoloFunction::Delete(
+                    _,
+                    ir::Operand::Var(obj),
+                    ir::Operand::Const(ir::Const::String(key))
+                )
+            } if obj == "obj" && key == "a"
         )
     }));
 
     assert!(out.iter().any(|instr| {
         matches!(
             instr,
-            ir::Instruction::Assign {
-                dest,
-                src: ir::Operand::Const(ir::Const::Boolean(true))
-            } if dest == "ok"
+            ir::Instruction::Call {
+                function: ir::SoloFunction::Delete(_, ir::Operand::Var(obj), ir::Operand::Var(key))
+            } if obj == "obj" && key == "k"
+        )
+    }));
+
+    assert!(out.iter().any(|instr| {
+        matches!(
+            instr,
+            ir::Instruction::Call {
+                function: ir::SoloFunction::DeleteVar(_, name)
+            } if name == "x"
         )
     }));
 }
@@ -371,23 +363,43 @@ fn translates_delete_member_to_undefined_and_true() {
 fn translates_prototype_style_paths() {
     let out = compile_ir("Foo.prototype.x = 1; var y = inst.x;");
 
+    // Foo.prototype is read, then x is set on it
+    let proto = out.iter().find_map(|instr| match instr {
+        ir::Instruction::Classic {
+            dest,
+            function:
+                ir::Function::GetProp(
+                    ir::Operand::Var(obj),
+                    ir::Operand::Const(ir::Const::String(key))
+                ),
+        } if obj == "Foo" && key == "prototype" => Some(dest.clone()),
+        _ => None,
+    });
+    let proto = proto.expect("no read of Foo.prototype");
+
     assert!(out.iter().any(|instr| {
         matches!(
             instr,
-            ir::Instruction::Assign {
-                dest,
-                src: ir::Operand::Const(ir::Const::Number(1.0))
-            } if dest == "Foo_prototype_x"
+            ir::Instruction::Call {
+                function: ir::SoloFunction::SetProp(
+                    ir::Operand::Var(obj),
+                    ir::Operand::Const(ir::Const::String(key)),
+                    ir::Operand::Const(ir::Const::Number(1.0))
+                )
+            } if *obj == proto && key == "x"
         )
     }));
 
     assert!(out.iter().any(|instr| {
         matches!(
             instr,
-            ir::Instruction::Assign {
-                dest,
-                src: ir::Operand::Var(src)
-            } if dest == "y" && src == "inst_x"
+            ir::Instruction::Classic {
+                function: ir::Function::GetProp(
+                    ir::Operand::Var(obj),
+                    ir::Operand::Const(ir::Const::String(key))
+                ),
+                ..
+            } if obj == "inst" && key == "x"
         )
     }));
 }
@@ -0,0 +1,151 @@
+use tinyjs::ir;
+
+mod common;
+
+use common::compile_ir;
+
+fn block_containing(
+    cfg: &ir::cfg::Cfg,
+    program: &ir::Program,
+    pred: impl Fn(&ir::Instruction) -> bool,
+) -> usize {
+    cfg.blocks
+        .iter()
+        .position(|b| b.instructions.iter().any(|&i| pred(&program.body[i])))
+        .expect("missing block")
+}
+
+#[test]
+fn splits_if_else_into_diamond() {
+    let program = compile_ir("var a = 1; if (a < 3) b = 1; else b = 2; c = b;");
+    let cfg = ir::cfg::Cfg::build(&program);
+
+    let then_block = block_containing(
+        &cfg,
+        &program,
+        |i| matches!(i, ir::Instruction::Assign { dest, src: ir::Operand::Const(ir::Const::Number(1.0)) } if dest == "b"),
+    );
+    let else_block = block_containing(
+        &cfg,
+        &program,
+        |i| matches!(i, ir::Instruction::Assign { dest, src: ir::Operand::Const(ir::Const::Number(2.0)) } if dest == "b"),
+    );
+    let join_block = block_containing(&cfg, &program, |i| i.dest() == Some("c"));
+
+    assert_eq!(cfg.blocks[then_block].successors, vec![join_block]);
+    assert_eq!(cfg.blocks[else_block].successors, vec![join_block]);
+    assert_eq!(cfg.blocks[join_block].predecessors.len(), 2);
+
+    let doms = cfg.dominators();
+    assert!(doms.dominates(0, join_block));
+    assert!(!doms.dominates(then_block, join_block));
+    assert!(!doms.dominates(else_block, join_block));
+
+    let frontiers = doms.frontiers(&cfg);
+    assert_eq!(frontiers[then_block], vec![join_block]);
+    assert_eq!(frontiers[else_block], vec![join_block]);
+}
+
+#[test]
+fn while_loop_has_back_edge_to_header() {
+    let program = compile_ir("var i = 0; while (i < 2) { i++; }");
+    let cfg = ir::cfg::Cfg::build(&program);
+
+    let header = block_containing(&cfg, &program, |i| {
+        matches!(
+            i,
+            ir::Instruction::Classic {
+                function: ir::Function::LessThan(..),
+                ..
+            }
+        )
+    });
+    let body = block_containing(&cfg, &program, |i| {
+        matches!(
+            i,
+            ir::Instruction::Classic {
+                function: ir::Function::Add(..),
+                ..
+            }
+        )
+    });
+
+    assert!(cfg.blocks[body].successors.contains(&header));
+    assert!(cfg.blocks[header].predecessors.contains(&body));
+
+    let doms = cfg.dominators();
+    assert_eq!(doms.immediate(body), Some(header));
+    assert!(doms.frontiers(&cfg)[body].contains(&header));
+}
+
+#[test]
+fn return_block_has_no_successor() {
+    let program = compile_ir("function f(a) { if (a) { return 1; } return 2; }");
+    let cfg = ir::cfg::Cfg::for_function(&program, "f").expect("missing function");
+
+    assert_eq!(cfg.name.as_deref(), Some("f"));
+    let returns: Vec<usize> = (0..cfg.blocks.len())
+        .filter(|&b| {
+            cfg.blocks[b].instructions.last().is_some_and(|&i| {
+                matches!(
+                    program.body[i],
+                    ir::Instruction::Call {
+                        function: ir::SoloFunction::Return(_)
+                    }
+                )
+            })
+        })
+        .collect();
+
+    assert_eq!(returns.len(), 2);
+    for b in returns {
+        assert!(cfg.blocks[b].successors.is_empty());
+    }
+}
+
+#[test]
+fn nested_functions_are_opaque_in_top_level() {
+    let program = compile_ir("var a = 1; function f() { while (true) {} } var b = 2;");
+    let cfg = ir::cfg::Cfg::build(&program);
+
+    assert_eq!(cfg.blocks.len(), 1);
+    assert_eq!(cfg.nested.len(), 1);
+}
+
+#[test]
+fn exports_graphviz_dot() {
+    let program = compile_ir("var i = 0; while (i < 2) { i++; }");
+    let cfg = ir::cfg::Cfg::build(&program);
+    let dot = cfg.to_dot(&program);
+
+    assert!(dot.starts_with("digraph \"<main>\" {"));
+    assert!(dot.contains("i = 0"));
+    let edges: usize = cfg.blocks.iter().map(|b| b.successors.len()).sum();
+    assert_eq!(dot.matches(" -> ").count(), edges);
+    assert!(dot.trim_end().ends_with('}'));
+}
+
+#[test]
+fn protected_blocks_reach_the_innermost_handler() {
+    let program = compile_ir(
+        "try { try { a = 1; } catch (e) { b = 1; } c = 1; } catch (f) { d = 1; } g = 1;",
+    );
+    let cfg = ir::cfg::Cfg::build(&program);
+
+    let catching = |name: &str| {
+        block_containing(
+            &cfg,
+            &program,
+            |i| matches!(i, ir::Instruction::Call { function: ir::SoloFunction::Catch(n) } if n == name),
+        )
+    };
+    let defining = |name: &str| block_containing(&cfg, &program, |i| i.dest() == Some(name));
+    let (inner, outer) = (catching("e"), catching("f"));
+
+    assert!(cfg.blocks[defining("a")].successors.contains(&inner));
+    assert!(!cfg.blocks[defining("a")].successors.contains(&outer));
+    assert!(cfg.blocks[defining("b")].successors.contains(&outer));
+    assert!(cfg.blocks[defining("c")].successors.contains(&outer));
+    assert!(!cfg.blocks[defining("d")].successors.contains(&outer));
+    assert!(!cfg.blocks[defining("g")].successors.contains(&outer));
+}
@@ -0,0 +1,156 @@
+use tinyjs::ir;
+use tinyjs::vm;
+
+mod common;
+
+use common::compile_ir;
+
+fn count_labels(program: &ir::Program) -> usize {
+    program
+        .body
+        .iter()
+        .filter(|instr| {
+            matches!(
+                instr,
+                ir::Instruction::Call {
+                    function: ir::SoloFunction::Label(_)
+                }
+            )
+        })
+        .count()
+}
+
+#[test]
+fn removes_unused_if_condition_label() {
+    let mut program = compile_ir("if (a < 3) b = 1; else b = 2;");
+    let before = count_labels(&program);
+
+    let removed = ir::dce::eliminate_dead_code(&mut program);
+
+    assert!(removed > 0);
+    // cond_label is never jumped to
+    assert_eq!(count_labels(&program), before - 1);
+}
+
+#[test]
+fn removes_code_after_return() {
+    let mut program = compile_ir("function f(a) { return a; }");
+
+    ir::dce::eliminate_dead_code(&mut program);
+
+    let ret_pos = program
+        .body
+        .iter()
+        .position(|instr| {
+            matches!(
+                instr,
+                ir::Instruction::Call {
+                    function: ir::SoloFunction::Return(_)
+                }
+            )
+        })
+        .expect("missing return");
+
+    assert!(matches!(
+        program.body.get(ret_pos + 1),
+        Some(ir::Instruction::Call {
+            function: ir::SoloFunction::FnEnd()
+        })
+    ));
+    assert!(!program.body.iter().any(|instr| {
+        matches!(instr, ir::Instruction::Classic { dest, .. } if dest.starts_with("__ret_"))
+    }));
+}
+
+#[test]
+fn removes_unused_temporaries_only() {
+    let mut program = compile_ir("!true; !a; var b = a * 2;");
+
+    ir::dce::eliminate_dead_code(&mut program);
+
+    let inverted: Vec<String> = program
+        .body
+        .iter()
+        .filter_map(|instr| match instr {
+            ir::Instruction::Classic {
+                function: ir::Function::Inv(op),
+                ..
+            } => Some(op.to_string()),
+            _ => None,
+        })
+        .collect();
+    // reading the undeclared `a` throws, the unused result stays
+    assert_eq!(inverted, vec!["a"]);
+    assert!(program.body.iter().any(|instr| {
+        matches!(
+            instr,
+            ir::Instruction::Classic {
+                function: ir::Function::Mul(..),
+                ..
+            }
+        )
+    }));
+    assert!(program.body.iter().any(|instr| instr.dest() == Some("b")));
+}
+
+#[test]
+fn keeps_loops_intact() {
+    let mut program = compile_ir("while (true) { continue; break; }");
+
+    ir::dce::eliminate_dead_code(&mut program);
+
+    // the break is dead but the loop jumps are not
+    let jumps = program
+        .body
+        .iter()
+        .filter(|instr| instr.jump_target().is_some())
+        .count();
+    assert_eq!(jumps, 3);
+}
+
+#[test]
+fn reports_zero_on_clean_program() {
+    let mut program = compile_ir("var a = 1; var b = a;");
+
+    assert_eq!(ir::dce::eliminate_dead_code(&mut program), 0);
+    // two assignments and their positions
+    assert_eq!(program.body.len(), 4);
+}
+
+#[test]
+fn preserves_program_results() {
+    let source = "var i = 0; var s = 0; while (i < 10) { i++; if (i == 3) { continue; } if (i == 7) { break; } s += i; }";
+
+    let mut original = vm::VM::new(vm::compile_to_bytecode(compile_ir(source)));
+    original.run().unwrap();
+
+    let mut program = compile_ir(source);
+    assert!(ir::dce::eliminate_dead_code(&mut program) > 0);
+    let mut optimized = vm::VM::new(vm::compile_to_bytecode(program));
+    optimized.run().unwrap();
+
+    for name in ["i", "s"] {
+        assert_eq!(
+            format!("{:?}", original.get_variable(name)),
+            format!("{:?}", optimized.get_variable(name))
+        );
+    }
+}
+
+#[test]
+fn keeps_unused_conversions() {
+    // a can be an object whose valueOf has side effects
+    let mut program = compile_ir("a + 2;");
+
+    ir::dce::eliminate_dead_code(&mut program);
+
+    assert!(program.body.iter().any(|instr| {
+        matches!(
+            instr,
+            ir::Instruction::Classic {
+                function: ir::Function::Add(..),
+                ..
+            }
+        )
+    }));
+}
@@ -0,0 +1,270 @@
+use tinyjs::ir;
+use tinyjs::vm;
+
+mod common;
+
+use common::compile_ir;
+
+fn run(program: ir::Program) -> vm::VM {
+    let mut machine = vm::VM::new(vm::compile_to_bytecode(program));
+    machine.run().unwrap();
+    machine
+}
+
+fn assert_same_results(source: &str, names: &[&str]) {
+    let original = run(compile_ir(source));
+    let converted = run(ir::ssa::round_trip(&compile_ir(source)));
+
+    for name in names {
+        assert_eq!(
+            format!("{:?}", original.get_variable(name)),
+            format!("{:?}", converted.get_variable(name)),
+            "{} differs after SSA round trip of '{}'",
+            name,
+            source
+        );
+    }
+}
+
+fn assign(dest: &str, src: ir::Operand) -> ir::Instruction {
+    ir::Instruction::Assign {
+        dest: dest.to_string(),
+        src,
+    }
+}
+
+fn var(name: &str) -> ir::Operand {
+    ir::Operand::Var(name.to_string())
+}
+
+fn num(n: f64) -> ir::Operand {
+    ir::Operand::Const(ir::Const::Number(n))
+}
+
+#[test]
+fn every_version_is_defined_once() {
+    let program = compile_ir("var i = 0; var s = 0; while (i < 5) { s += i; i++; } var r = s;");
+    let cfg = ir::cfg::Cfg::build(&program);
+    let ssa = ir::ssa::construct(&program, &cfg);
+
+    let mut defined = std::collections::HashSet::new();
+    for block in &ssa.blocks {
+        for phi in &block.phis {
+            assert!(
+                defined.insert(phi.dest.clone()),
+                "{} defined twice",
+                phi.dest
+            );
+        }
+        for instr in &block.instructions {
+            if let Some(dest) = instr.dest() {
+                assert!(defined.insert(dest.to_string()), "{} defined twice", dest);
+            }
+        }
+    }
+
+    assert!(defined.contains("i#1"));
+    assert!(defined.contains("s#1"));
+}
+
+#[test]
+fn places_phis_at_loop_header() {
+    let program = compile_ir("var i = 0; while (i < 5) { i++; }");
+    let cfg = ir::cfg::Cfg::build(&program);
+    let ssa = ir::ssa::construct(&program, &cfg);
+
+    let phis: Vec<&ir::ssa::Phi> = ssa
+        .blocks
+        .iter()
+        .flat_map(|b| b.phis.iter())
+        .filter(|phi| ir::ssa::base_name(&phi.dest) == "i")
+        .collect();
+
+    assert_eq!(phis.len(), 1);
+    assert_eq!(phis[0].args.len(), 2);
+    assert!(
+        phis[0]
+            .args
+            .iter()
+            .all(|(_, arg)| matches!(arg, ir::Operand::Var(v) if v.starts_with("i#")))
+    );
+}
+
+#[test]
+fn round_trip_keeps_variable_names() {
+    let program = ir::ssa::round_trip(&compile_ir("var i = 0; while (i < 5) { i++; }"));
+
+    assert!(
+        program
+            .body
+            .iter()
+            .all(|instr| !instr.dest().is_some_and(|d| d.contains('#')))
+    );
+}
+
+#[test]
+fn round_trip_preserves_loops() {
+    assert_same_results(
+        "var i = 0; var s = 0; while (i < 10) { i++; if (i == 3) { continue; } if (i == 7) { break; } s += i; }",
+        &["i", "s"],
+    );
+    assert_same_results(
+        "var s = 0; for (var i = 0; i < 4; i++) { var j = 0; while (j < i) { s += j; j++; } }",
+        &["i", "j", "s"],
+    );
+}
+
+#[test]
+fn round_trip_preserves_branches() {
+    assert_same_results(
+        "var a = 4; var b; if (a < 3) { b = 1; } else { b = 2; } var c = a > 2 ? b : 10; var d = !c;",
+        &["a", "b", "c", "d"],
+    );
+    assert_same_results(
+        "var x = 1; if (x == 2) { x = 5; } var y = x - 1;",
+        &["x", "y"],
+    );
+}
+
+#[test]
+fn round_trip_splits_critical_edges() {
+    // L1 loops on itself with a JumpIf: the back edge is critical
+    let body = vec![
+        assign("x", num(0.0)),
+        ir::Instruction::Call {
+            function: ir::SoloFunction::Label(1),
+        },
+        ir::Instruction::Classic {
+            dest: "x".to_string(),
+            function: ir::Function::Add(var("x"), num(1.0)),
+        },
+        ir::Instruction::Classic {
+            dest: "t".to_string(),
+            function: ir::Function::LessThan(var("x"), num(3.0)),
+        },
+        ir::Instruction::Call {
+            function: ir::SoloFunction::JumpIf(var("t"), 1),
+        },
+        assign("y", var("x")),
+    ];
+
+    let original = run(ir::Program { body: body.clone() });
+    let converted = run(ir::ssa::round_trip(&ir::Program { body }));
+
+    for name in ["x", "t", "y"] {
+        assert_eq!(
+            format!("{:?}", original.get_variable(name)),
+            format!("{:?}", converted.get_variable(name))
+        );
+    }
+}
+
+#[test]
+fn round_trip_handles_swaps() {
+    // phis reading each other must be copied in parallel
+    let body = vec![
+        assign("a", num(1.0)),
+        assign("b", num(2.0)),
+        assign("n", num(0.0)),
+        ir::Instruction::Call {
+            function: ir::SoloFunction::Label(1),
+        },
+        ir::Instruction::Classic {
+            dest: "t".to_string(),
+            function: ir::Function::LessThan(var("n"), num(3.0)),
+        },
+        ir::Instruction::Call {
+            function: ir::SoloFunction::JumpIf(var("t"), 2),
+        },
+        ir::Instruction::Call {
+            function: ir::SoloFunction::Jump(3),
+        },
+        ir::Instruction::Call {
+            function: ir::SoloFunction::Label(2),
+        },
+        assign("tmp", var("a")),
+        assign("a", var("b")),
+        assign("b", var("tmp")),
+        ir::Instruction::Classic {
+            dest: "n".to_string(),
+            function: ir::Function::Add(var("n"), num(1.0)),
+        },
+        ir::Instruction::Call {
+            function: ir::SoloFunction::Jump(1),
+        },
+        ir::Instruction::Call {
+            function: ir::SoloFunction::Label(3),
+        },
+    ];
+
+    let converted = run(ir::ssa::round_trip(&ir::Program { body }));
+
+    assert_eq!(format!("{:?}", converted.get_variable("a")), "Number(2.0)");
+    assert_eq!(format!("{:?}", converted.get_variable("b")), "Number(1.0)");
+}
+
+#[test]
+fn catch_merges_the_values_of_every_protected_block() {
+    let source = "var x = 1; var t = 0; try { t = x; x = 2; if (t == 1) throw 0; x = 3; } catch (e) { y = x*10 + t; }";
+    let program = compile_ir(source);
+    let cfg = ir::cfg::Cfg::build(&program);
+    let ssa = ir::ssa::construct(&program, &cfg);
+
+    let defining = |name: &str| {
+        ssa.blocks
+            .iter()
+            .position(|b| b.instructions.iter().any(|i| i.dest() == Some(name)))
+            .expect("missing definition")
+    };
+    let handler = defining("e#1");
+    let predecessors = &ssa.blocks[handler].predecessors;
+
+    // the entry installs the handler, then every definition of the try body
+    // ends a block reaching it
+    for block in [0, defining("t#2"), defining("x#2"), defining("x#3")] {
+        assert!(
+            predecessors.contains(&block),
+            "B{} doesn't reach the catch",
+            block
+        );
+    }
+    let after_try = ssa
+        .blocks
+        .iter()
+        .position(|b| {
+            b.instructions.iter().any(|i| {
+                matches!(
+                    i,
+                    ir::Instruction::Call {
+                        function: ir::SoloFunction::TryEnd()
+                    }
+                )
+            })
+        })
+        .unwrap();
+    assert!(!predecessors.contains(&after_try));
+
+    let incoming = |base: &str| {
+        let phi = ssa.blocks[handler]
+            .phis
+            .iter()
+            .find(|phi| ir::ssa::base_name(&phi.dest) == base)
+            .expect("missing phi");
+        assert_eq!(phi.args.len(), predecessors.len());
+        let mut values: Vec<String> = phi
+            .args
+            .iter()
+            .map(|(_, arg)| match arg {
+                ir::Operand::Var(v) => v.clone(),
+                other => panic!("unexpected phi argument {:?}", other),
+            })
+            .collect();
+        values.sort();
+        values.dedup();
+        values
+    };
+    assert_eq!(incoming("x"), vec!["x#1", "x#2", "x#3"]);
+    assert_eq!(incoming("t"), vec!["t#1", "t#2"]);
+
+    assert_same_results(source, &["x", "t", "y"]);
+}
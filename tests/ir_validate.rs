@@ -0,0 +1,116 @@
+use tinyjs::ir;
+
+mod common;
+
+use common::compile_ir;
+
+fn call(function: ir::SoloFunction) -> ir::Instruction {
+    ir::Instruction::Call { function }
+}
+
+fn messages(body: Vec<ir::Instruction>) -> Vec<String> {
+    ir::validate::validate(&ir::Program { body })
+        .into_iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+#[test]
+fn accepts_compiled_programs() {
+    let sources = [
+        "var i = 0; while (i < 5) { if (i == 3) { break; } i++; }",
+        "for (var i = 0; i < 3; i++) { a += i; }",
+        "function f(a, b) { if (a) { return a; } return b; } f(1, 2);",
+        "for (k in obj) { n = k; }",
+        "var x = a ? 1 : 2; with (obj) { a = b; }",
+    ];
+
+    for source in sources {
+        let diagnostics = ir::validate::validate(&compile_ir(source));
+        assert!(diagnostics.is_empty(), "{}: {:?}", source, diagnostics);
+    }
+}
+
+#[test]
+fn reports_duplicate_and_undefined_labels() {
+    let out = messages(vec![
+        call(ir::SoloFunction::Label(1)),
+        call(ir::SoloFunction::Label(1)),
+        call(ir::SoloFunction::Jump(2)),
+    ]);
+
+    assert_eq!(
+        out,
+        vec![
+            "instruction 1: label 1 is already defined at instruction 0",
+            "instruction 2: jump to undefined label 2",
+        ]
+    );
+}
+
+#[test]
+fn reports_jumps_across_functions() {
+    let out = messages(vec![
+        call(ir::SoloFunction::FnStart("f".to_string(), 0)),
+        call(ir::SoloFunction::Label(1)),
+        call(ir::SoloFunction::FnEnd()),
+        call(ir::SoloFunction::Jump(1)),
+    ]);
+
+    assert_eq!(
+        out,
+        vec!["instruction 3: jump to label 1 defined in another function"]
+    );
+}
+
+#[test]
+fn reports_unbalanced_functions() {
+    let out = messages(vec![
+        call(ir::SoloFunction::FnEnd()),
+        call(ir::SoloFunction::FnStart("f".to_string(), 0)),
+    ]);
+
+    assert_eq!(
+        out,
+        vec![
+            "instruction 0: FnEnd without matching FnStart",
+            "instruction 1: FnStart without matching FnEnd",
+        ]
+    );
+}
+
+#[test]
+fn reports_return_outside_function() {
+    let out = messages(vec![
+        call(ir::SoloFunction::FnStart("f".to_string(), 0)),
+        call(ir::SoloFunction::Return(None)),
+        call(ir::SoloFunction::FnEnd()),
+        call(ir::SoloFunction::Return(None)),
+    ]);
+
+    assert_eq!(out, vec!["instruction 3: Return outside of a function"]);
+}
+
+#[test]
+fn reports_for_in_next_without_start() {
+    let iter = ir::Operand::Var("it".to_string());
+    let out = messages(vec![
+        call(ir::SoloFunction::ForInNext("k".to_string(), iter.clone())),
+        call(ir::SoloFunction::ForInStart(
+            "it".to_string(),
+            ir::Operand::Var("o".to_string()),
+        )),
+        call(ir::SoloFunction::ForInNext("k".to_string(), iter.clone())),
+        call(ir::SoloFunction::FnStart("f".to_string(), 0)),
+        call(ir::SoloFunction::ForInNext("k".to_string(), iter)),
+        call(ir::SoloFunction::FnEnd()),
+    ]);
+
+    assert_eq!(
+        out,
+        vec![
+            "instruction 0: ForInNext on 'it' which is not created by a ForInStart",
+            "instruction 4: ForInNext on 'it' which is not created by a ForInStart",
+        ]
+    );
+}
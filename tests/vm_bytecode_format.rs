@@ -0,0 +1,168 @@
+use tinyjs::vm;
+use tinyjs::vm::format::LoadError;
+
+mod common;
+
+use common::compile;
+
+const SOURCE: &str = "var s = 'hi'; var n = 0; var o = { a: 1.5, b: null }; function f(x) { return x; } while (n < 4) { n++; if (n == 2) { continue; } s = s + n; } var k; for (k in o) { delete o.b; }";
+
+#[test]
+fn round_trips_instructions() {
+    let bc = compile(SOURCE);
+
+    let mut bytes = Vec::new();
+    bc.write_to(&mut bytes).unwrap();
+    let loaded = vm::Bytecode::read_from(&mut bytes.as_slice()).unwrap();
+
+    assert_eq!(loaded.instructions, bc.instructions);
+    assert_eq!(loaded.constants, bc.constants);
+    assert_eq!(loaded.names, bc.names);
+    assert!(!bc.temporaries.is_empty());
+    assert_eq!(loaded.temporaries, bc.temporaries);
+    assert_eq!(loaded.debug, bc.debug);
+}
+
+#[test]
+fn round_trips_debug_info() {
+    let mut bc = compile(SOURCE);
+    bc.debug = Some(vm::DebugInfo {
+        file: "test.js".to_string(),
+        source: SOURCE.to_string(),
+        lines: vec![
+            vm::LineEntry {
+                pc: 0,
+                line: 1,
+                column: 1,
+            },
+            vm::LineEntry {
+                pc: 4,
+                line: 1,
+                column: 15,
+            },
+            vm::LineEntry {
+                pc: 9,
+                line: 70000,
+                column: 300,
+            },
+            vm::LineEntry {
+                pc: 10,
+                line: 2,
+                column: 1,
+            },
+        ],
+    });
+
+    let loaded = vm::Bytecode::from_bytes(&bc.to_bytes()).unwrap();
+
+    assert_eq!(loaded.debug, bc.debug);
+}
+
+#[test]
+fn sorts_the_line_table_by_pc() {
+    let entry = |pc, line| vm::LineEntry {
+        pc,
+        line,
+        column: 1,
+    };
+    let mut bc = compile(SOURCE);
+    bc.debug = Some(vm::DebugInfo {
+        file: "test.js".to_string(),
+        source: SOURCE.to_string(),
+        lines: vec![entry(9, 3), entry(0, 1), entry(4, 2)],
+    });
+
+    let loaded = vm::Bytecode::from_bytes(&bc.to_bytes()).unwrap();
+
+    assert_eq!(
+        loaded.debug.unwrap().lines,
+        vec![entry(0, 1), entry(4, 2), entry(9, 3)]
+    );
+}
+
+#[test]
+fn loaded_program_runs_the_same() {
+    let mut original = vm::VM::new(compile(SOURCE));
+    original.run().unwrap();
+
+    let loaded = vm::Bytecode::from_bytes(&compile(SOURCE).to_bytes()).unwrap();
+    let mut machine = vm::VM::new(loaded);
+    machine.run().unwrap();
+
+    for name in ["s", "n"] {
+        assert_eq!(
+            format!("{:?}", original.get_variable(name)),
+            format!("{:?}", machine.get_variable(name))
+        );
+    }
+}
+
+#[test]
+fn strings_are_stored_once() {
+    let once = compile("var a = 'a long string literal';").to_bytes();
+    let twice =
+        compile("var a = 'a long string literal'; var b = 'a long string literal';").to_bytes();
+
+    // the second copy only costs a few opcodes and a name
+    assert!(twice.len() - once.len() < "a long string literal".len());
+}
+
+#[test]
+fn rejects_bad_header() {
+    let bytes = compile(SOURCE).to_bytes();
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[0] = b'X';
+    assert!(matches!(
+        vm::Bytecode::from_bytes(&wrong_magic),
+        Err(LoadError::BadMagic)
+    ));
+
+    assert_eq!(bytes[4..6], [1, 0]);
+    let mut wrong_version = bytes.clone();
+    wrong_version[4] = 0xff;
+    assert!(matches!(
+        vm::Bytecode::from_bytes(&wrong_version),
+        Err(LoadError::UnsupportedVersion(_))
+    ));
+
+    assert!(matches!(
+        vm::Bytecode::from_bytes(b"TJ"),
+        Err(LoadError::BadMagic)
+    ));
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let bytes = compile(SOURCE).to_bytes();
+
+    for len in 4..bytes.len() {
+        assert!(
+            vm::Bytecode::from_bytes(&bytes[..len]).is_err(),
+            "accepted a file cut at {} bytes",
+            len
+        );
+    }
+}
+
+#[test]
+fn rejects_corrupted_files() {
+    let bytes = compile(SOURCE).to_bytes();
+
+    for i in 8..bytes.len() {
+        let mut corrupted = bytes.clone();
+        corrupted[i] ^= 0x40;
+        assert!(
+            vm::Bytecode::from_bytes(&corrupted).is_err(),
+            "accepted a file with byte {} flipped",
+            i
+        );
+    }
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(matches!(
+        vm::Bytecode::from_bytes(&trailing),
+        Err(LoadError::Corrupted(_))
+    ));
+}
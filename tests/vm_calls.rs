@@ -0,0 +1,59 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tinyjs::vm;
+use tinyjs::vm::object::Object;
+
+mod common;
+
+use common::{log_of, machine, run_log};
+
+// `name` property of the object the method is called on
+fn name_of(_: &mut vm::VM, this: &vm::Value, _: &[vm::Value]) -> vm::NativeResult {
+    match this {
+        vm::Value::Object(object) => Ok(object
+            .borrow()
+            .get("name")
+            .cloned()
+            .unwrap_or(vm::Value::Undefined)),
+        _ => Ok(vm::Value::Undefined),
+    }
+}
+
+#[test]
+fn call_results_can_be_used_in_expressions() {
+    let log = run_log(
+        "function two() { return 2; }\nfunction inc(a) { return a + 1; }\n\
+         var log = two() * 10 + inc(two());",
+    );
+
+    assert_eq!(log, "Number(23.0)");
+}
+
+#[test]
+fn calls_as_statements_leave_the_stack_empty() {
+    let mut machine = machine("function two() { return 2; }\ntwo();\ntwo();");
+
+    let result = machine.run().unwrap();
+
+    assert!(matches!(result, vm::Value::Undefined), "{:?}", result);
+}
+
+#[test]
+fn methods_receive_the_object_as_this() {
+    let mut object = Object::new();
+    object.insert("name".to_string(), vm::Value::String("o".to_string()));
+    object.insert("nameOf".to_string(), vm::Value::NativeMethod(name_of));
+    let mut machine = machine("var log = o.nameOf(0) + \"!\";");
+    machine.set_global("o", vm::Value::Object(Rc::new(RefCell::new(object))));
+
+    assert_eq!(log_of(machine), "String(\"o!\")");
+}
+
+#[test]
+fn script_functions_can_be_called_as_methods() {
+    let log =
+        run_log("function inc(a) { return a + 1; }\nvar o = {inc: inc};\nvar log = o.inc(1);");
+
+    assert_eq!(log, "Number(2.0)");
+}
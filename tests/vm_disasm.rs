@@ -0,0 +1,150 @@
+use tinyjs::ir::Const;
+use tinyjs::vm;
+use tinyjs::vm::OpCode;
+use tinyjs::vm::disasm;
+
+// indices in the pools built by `bytecode`
+const A: u32 = 0;
+const F: u32 = 1;
+const X: u32 = 2;
+const Y: u32 = 3;
+const ONE: u32 = 0;
+const TWO: u32 = 1;
+const STRING: u32 = 2;
+
+fn bytecode(instructions: Vec<OpCode>) -> vm::Bytecode {
+    let mut code = vm::Bytecode::new();
+    code.names = ["a", "f", "x", "y"].map(String::from).to_vec();
+    code.constants = vec![
+        Const::Number(1.0),
+        Const::Number(2.0),
+        Const::String("a".to_string()),
+    ];
+    code.instructions = instructions;
+    code
+}
+
+#[test]
+fn prints_absolute_jump_targets() {
+    let code = bytecode(vec![OpCode::Nop, OpCode::JumpIfNot(4), OpCode::Jump(-2)]);
+
+    assert_eq!(disasm::format_instruction(&code, 1), "JumpIfNot -> 0005");
+    assert_eq!(disasm::format_instruction(&code, 2), "Jump -> 0000");
+}
+
+#[test]
+fn resolves_pool_indices() {
+    let code = bytecode(vec![
+        OpCode::Const(STRING),
+        OpCode::Call { name: F, argc: 2 },
+        OpCode::Const(9),
+        OpCode::Load(9),
+    ]);
+
+    assert_eq!(disasm::format_instruction(&code, 0), "Const \"a\"");
+    assert_eq!(disasm::format_instruction(&code, 1), "Call f/2");
+    assert_eq!(disasm::format_instruction(&code, 2), "Const <constant #9>");
+    assert_eq!(disasm::format_instruction(&code, 3), "Load <name #9>");
+}
+
+#[test]
+fn lists_every_instruction_with_its_address() {
+    let code = bytecode(vec![OpCode::Const(ONE), OpCode::Store(X), OpCode::Halt]);
+
+    assert_eq!(
+        disasm::disassemble(&code),
+        "0000  Const 1\n0001  Store x\n0002  Halt\n"
+    );
+}
+
+#[test]
+fn indents_function_bodies() {
+    let code = bytecode(vec![
+        OpCode::Jump(4),
+        OpCode::FnStart {
+            name: F,
+            argc: 1,
+            body_offset: 3,
+        },
+        OpCode::Load(A),
+        OpCode::Return { has_value: true },
+        OpCode::Halt,
+    ]);
+
+    let listing = disasm::disassemble(&code);
+
+    assert_eq!(
+        listing,
+        "0000  Jump -> 0004\n\
+         0001  FnStart f/1 (end 0004)\n\
+         0002    Load a\n\
+         0003    Return value\n\
+         \x20     ; end of f\n\
+         0004  Halt\n"
+    );
+}
+
+#[test]
+fn closes_functions_at_fn_end() {
+    let code = bytecode(vec![
+        OpCode::FnStart {
+            name: F,
+            argc: 0,
+            body_offset: 0,
+        },
+        OpCode::Return { has_value: false },
+        OpCode::FnEnd,
+        OpCode::Halt,
+    ]);
+
+    let listing = disasm::disassemble(&code);
+
+    assert!(listing.contains("0001    Return\n"));
+    assert!(listing.contains("0002    FnEnd\n      ; end of f\n0003  Halt\n"));
+}
+
+#[test]
+fn interleaves_source_lines() {
+    let mut code = bytecode(vec![
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Const(TWO),
+        OpCode::Store(Y),
+        OpCode::Halt,
+    ]);
+    code.debug = Some(vm::DebugInfo {
+        file: "test.js".to_string(),
+        source: "var x = 1;\n  var y = 2;\n".to_string(),
+        lines: vec![
+            vm::LineEntry {
+                pc: 0,
+                line: 1,
+                column: 9,
+            },
+            vm::LineEntry {
+                pc: 1,
+                line: 1,
+                column: 1,
+            },
+            vm::LineEntry {
+                pc: 2,
+                line: 2,
+                column: 11,
+            },
+        ],
+    });
+
+    let listing = disasm::disassemble(&code);
+
+    assert_eq!(
+        listing,
+        "; test.js\n\
+         \x20     ; 1 | var x = 1;\n\
+         0000  Const 1\n\
+         0001  Store x\n\
+         \x20     ; 2 | var y = 2;\n\
+         0002  Const 2\n\
+         0003  Store y\n\
+         0004  Halt\n"
+    );
+}
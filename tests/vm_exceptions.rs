@@ -0,0 +1,149 @@
+use tinyjs::ast;
+use tinyjs::vm;
+use tinyjs::vm::error::ErrorKind;
+
+mod common;
+
+use common::{compile, parse_program};
+
+fn run(source: &str) -> vm::VM {
+    let mut machine = vm::VM::new(compile(source));
+    if let Err(err) = machine.run() {
+        panic!("{}", err);
+    }
+    machine
+}
+
+fn variable(machine: &vm::VM, name: &str) -> String {
+    format!("{:?}", machine.get_variable(name))
+}
+
+#[test]
+fn parses_try_catch_finally() {
+    let program = parse_program("try { a(); } catch (e) { b(); } finally { c(); }");
+
+    let ast::Stmt::Spanned(_, stmt) = &program.body[0] else {
+        panic!("expected a statement, got {:?}", program.body[0]);
+    };
+    let ast::Stmt::Try {
+        block,
+        catch,
+        finally,
+    } = stmt.as_ref()
+    else {
+        panic!("expected a try statement, got {:?}", stmt);
+    };
+    assert!(matches!(block.as_ref(), ast::Stmt::Block(b) if b.len() == 1));
+    assert!(matches!(catch, Some((name, _)) if name == "e"));
+    assert!(finally.is_some());
+
+    let program = parse_program("throw 1;");
+    assert!(matches!(
+        &program.body[0],
+        ast::Stmt::Spanned(_, stmt) if matches!(stmt.as_ref(), ast::Stmt::Throw(_))
+    ));
+}
+
+#[test]
+fn catches_thrown_values() {
+    let machine = run("var r = 0;\ntry { r = 1; throw \"boom\"; r = 2; } catch (e) { r = e; }");
+
+    assert_eq!(variable(&machine, "r"), "String(\"boom\")");
+}
+
+#[test]
+fn catches_errors_raised_by_the_vm() {
+    let machine = run("var name = 0;\ntry { missing(); } catch (e) { name = e; }");
+
+    let vm::Value::Object(error) = machine.get_variable("name") else {
+        panic!("not an error object: {}", variable(&machine, "name"));
+    };
+    assert_eq!(
+        format!("{:?}", error.borrow().get("name")),
+        "Some(String(\"ReferenceError\"))"
+    );
+}
+
+#[test]
+fn unwinds_the_calls_to_the_handler() {
+    let machine = run(
+        "function f(a) {\n  g(a);\n}\nfunction g(b) {\n  throw b;\n}\n\
+         var r = 0;\ntry { f(5); } catch (e) { r = e; }",
+    );
+
+    assert_eq!(variable(&machine, "r"), "Number(5.0)");
+    // the frames of f and g are gone with their locals
+    assert_eq!(variable(&machine, "arg0"), "Undefined");
+
+    let err = vm::VM::new(compile(
+        "function f() {\n  throw 1;\n}\ntry { f(); } catch (e) {}\nvar a = 1;\na();",
+    ))
+    .run()
+    .unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::TypeError));
+    assert_eq!(err.stack.len(), 1);
+}
+
+#[test]
+fn unwinds_the_scope_chain() {
+    let mut machine = vm::VM::new(compile(
+        "var o = {z: 1};\ntry { with (o) { throw 0; } } catch (e) {}\nvar y = z;",
+    ));
+
+    let err = machine.run().unwrap_err();
+    assert_eq!(err.summary(), "ReferenceError: z is not defined");
+}
+
+#[test]
+fn runs_finally_on_every_exit() {
+    let machine = run("var log = \"\";\n\
+         try { log = log + \"a\"; } finally { log = log + \"b\"; }\n\
+         try { try { throw 1; } finally { log = log + \"c\"; } } catch (e) { log = log + \"d\"; }\n\
+         var i = 0;\n\
+         while (i < 5) {\n\
+           try { i = i + 1; if (i == 1) continue; if (i == 3) break; } finally { log = log + i; }\n\
+         }");
+
+    assert_eq!(variable(&machine, "log"), "String(\"abcd123\")");
+}
+
+#[test]
+fn finally_rethrows_what_catch_raises() {
+    let mut machine = vm::VM::new(compile(
+        "var log = \"\";\ntry { throw 1; } catch (e) { log = log + e; throw 2; } finally { log = log + \"f\"; }",
+    ));
+
+    let err = machine.run().unwrap_err();
+    assert_eq!(err.summary(), "2");
+    assert_eq!(variable(&machine, "log"), "String(\"1f\")");
+}
+
+#[test]
+fn returning_removes_the_handlers_of_the_function() {
+    let err = vm::VM::new(compile(
+        "function f() {\n  try { return 1; } catch (e) {}\n}\nf();\nthrow \"uncaught\";",
+    ))
+    .run()
+    .unwrap_err();
+
+    assert_eq!(err.summary(), "uncaught");
+}
+
+#[test]
+fn handlers_survive_the_bytecode_passes() {
+    let mut code = compile(
+        "var r = 0;\ntry { var t = 1; t = 2; missing(); } catch (e) { r = t; } finally { r = r + 1; }",
+    );
+
+    assert_eq!(vm::verify::verify(&code), vec![]);
+    vm::peephole::optimize(&mut code);
+    assert_eq!(vm::verify::verify(&code), vec![]);
+
+    let mut code = vm::Bytecode::from_bytes(&code.to_bytes()).unwrap();
+    assert_eq!(vm::verify::verify(&code), vec![]);
+    vm::peephole::optimize(&mut code);
+
+    let mut machine = vm::VM::new(code);
+    machine.run().unwrap();
+    assert_eq!(variable(&machine, "r"), "Number(3.0)");
+}
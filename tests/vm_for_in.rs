@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tinyjs::vm;
+use tinyjs::vm::object::{Attributes, Object};
+
+mod common;
+
+use common::{log_of, machine};
+
+// Value of `log` once the program ran with the global `o`
+fn run_log(source: &str, o: Object) -> String {
+    let mut machine = machine(source);
+    machine.set_global("o", vm::Value::Object(Rc::new(RefCell::new(o))));
+    log_of(machine)
+}
+
+fn object(keys: &[&str]) -> Object {
+    let mut object = Object::new();
+    for key in keys {
+        object.insert(key.to_string(), vm::Value::Number(1.0));
+    }
+    object
+}
+
+// clear(o) removes all the own properties of o
+fn clear(_: &mut vm::VM, args: &[vm::Value]) -> vm::NativeResult {
+    if let Some(vm::Value::Object(object)) = args.first() {
+        let mut object = object.borrow_mut();
+        let keys: Vec<String> = object.keys().cloned().collect();
+        for key in keys {
+            object.remove(&key);
+        }
+    }
+    Ok(vm::Value::Undefined)
+}
+
+const LOG_KEYS: &str = "var log = \"\";\nvar k;\nfor (k in o) log = log + k + \",\";";
+
+#[test]
+fn visits_array_indices_in_order() {
+    let log = run_log(
+        "var log = \"\";\nvar a = [5, 6, 7];\nvar k;\nfor (k in a) log = log + k;",
+        Object::new(),
+    );
+
+    assert_eq!(log, "String(\"012\")");
+}
+
+#[test]
+fn visits_own_then_inherited_properties_once() {
+    let prototype = Rc::new(RefCell::new(object(&["x", "y"])));
+    let mut o = Object::with_prototype(prototype);
+    o.insert("x".to_string(), vm::Value::Number(2.0));
+
+    assert_eq!(run_log(LOG_KEYS, o), "String(\"x,y,\")");
+}
+
+#[test]
+fn skips_dont_enum_properties() {
+    let prototype = Rc::new(RefCell::new(object(&["hidden", "inherited"])));
+    let mut o = Object::with_prototype(prototype);
+    o.define("hidden".to_string(), vm::Value::Null, Attributes::DONT_ENUM);
+    o.define("own".to_string(), vm::Value::Null, Attributes::DONT_ENUM);
+
+    // the DontEnum own property shadows the enumerable inherited one
+    assert_eq!(run_log(LOG_KEYS, o), "String(\"inherited,\")");
+}
+
+#[test]
+fn skips_properties_deleted_during_the_loop() {
+    let mut machine = machine("var log = \"\";\nvar k;\nfor (k in o) { log = log + k; clear(o); }");
+    machine.set_global(
+        "o",
+        vm::Value::Object(Rc::new(RefCell::new(object(&["a", "b", "c"])))),
+    );
+    machine.set_global("clear", vm::Value::NativeFunction(clear));
+    machine.run().unwrap();
+
+    assert_eq!(
+        format!("{:?}", machine.get_variable("log")),
+        "String(\"a\")"
+    );
+}
+
+#[test]
+fn inherited_properties_stay_visible_after_deleting_own_ones() {
+    let prototype = Rc::new(RefCell::new(object(&["a"])));
+    let mut o = Object::with_prototype(prototype);
+    o.insert("b".to_string(), vm::Value::Null);
+    o.insert("a".to_string(), vm::Value::Null);
+    let mut machine = machine("var log = \"\";\nvar k;\nfor (k in o) { clear(o); log = log + k; }");
+    machine.set_global("o", vm::Value::Object(Rc::new(RefCell::new(o))));
+    machine.set_global("clear", vm::Value::NativeFunction(clear));
+    machine.run().unwrap();
+
+    assert_eq!(
+        format!("{:?}", machine.get_variable("log")),
+        "String(\"ba\")"
+    );
+}
+
+#[test]
+fn visits_properties_in_insertion_order() {
+    let log = run_log(
+        "var log = \"\";\nvar p = {b: 1, a: 2, c: 3};\nvar k;\nfor (k in p) log = log + k;",
+        Object::new(),
+    );
+    assert_eq!(log, "String(\"bac\")");
+
+    // setting an existing property keeps its place
+    let mut o = object(&["z", "y", "x"]);
+    o.insert("z".to_string(), vm::Value::Null);
+    assert_eq!(run_log(LOG_KEYS, o), "String(\"z,y,x,\")");
+}
+
+#[test]
+fn visits_assigned_properties_in_insertion_order() {
+    let log = run_log(
+        "var log = \"\";\nvar p = {};\n\
+         p.b = 1;\np.a = 2;\np[\"10\"] = 3;\np.c = 4;\np[\"2\"] = 5;\n\
+         p.b = 6;\ndelete p.a;\np.a = 7;\n\
+         var k;\nfor (k in p) log = log + k + \",\";",
+        Object::new(),
+    );
+
+    // reassigning keeps the place, deleting then assigning moves to the end
+    assert_eq!(log, "String(\"2,10,b,c,a,\")");
+}
+
+#[test]
+fn visits_array_index_names_first_in_increasing_order() {
+    let o = object(&["z", "10", "a", "2", "01", "4294967295"]);
+
+    assert_eq!(run_log(LOG_KEYS, o), "String(\"2,10,z,a,01,4294967295,\")");
+}
+
+#[test]
+fn nested_loops_keep_their_own_iterators() {
+    let log = run_log(
+        "var log = \"\";\nvar a = [1, 2];\nvar i;\nvar j;\n\
+         for (i in a) for (j in a) log = log + i + j;\n\
+         for (i in a) log = log + i;",
+        Object::new(),
+    );
+
+    assert_eq!(log, "String(\"0001101101\")");
+}
@@ -0,0 +1,88 @@
+use tinyjs::vm;
+use tinyjs::vm::OpCode;
+
+mod common;
+
+use common::compile;
+
+// Positions of the instructions storing into `name`
+fn store_positions(code: &vm::Bytecode, name: &str) -> Vec<Option<(u32, u32)>> {
+    let debug = code.debug.as_ref().unwrap();
+    code.instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| matches!(op, OpCode::Store(n) if code.name(*n) == name))
+        .map(|(pc, _)| debug.position(pc))
+        .collect()
+}
+
+const SOURCE: &str = "var a = 1;\nvar b = 2;\n\nif (a) {\n  b = 3;\n}\nwhile (a < 3)\n    a++;\n";
+
+#[test]
+fn maps_instructions_to_their_statement() {
+    let code = compile(SOURCE);
+
+    assert_eq!(store_positions(&code, "a")[0], Some((1, 1)));
+    assert_eq!(
+        store_positions(&code, "b"),
+        vec![Some((2, 1)), Some((5, 3))]
+    );
+}
+
+#[test]
+fn keeps_one_entry_per_statement() {
+    let code = compile(SOURCE);
+    let lines = &code.debug.as_ref().unwrap().lines;
+
+    assert!(lines.windows(2).all(|w| w[0].pc < w[1].pc));
+    let positions: Vec<(u32, u32)> = lines.iter().map(|e| (e.line, e.column)).collect();
+    assert_eq!(
+        positions,
+        vec![(1, 1), (2, 1), (4, 1), (5, 3), (7, 1), (8, 5)]
+    );
+}
+
+#[test]
+fn loop_conditions_belong_to_the_loop() {
+    let code = compile(SOURCE);
+    let debug = code.debug.as_ref().unwrap();
+
+    // the backward jump goes to the condition, on the line of the `while`
+    let (pc, offset) = code
+        .instructions
+        .iter()
+        .enumerate()
+        .find_map(|(pc, op)| match op {
+            OpCode::Jump(offset) if *offset < 0 => Some((pc, *offset)),
+            _ => None,
+        })
+        .unwrap();
+    let target = (pc as i32 + offset) as usize;
+
+    assert_eq!(debug.position(target), Some((7, 1)));
+    assert_eq!(debug.position(pc), Some((8, 5)));
+}
+
+#[test]
+fn survives_peephole_optimisation() {
+    let mut code = compile(SOURCE);
+    let before: Vec<_> = ["a", "b"]
+        .iter()
+        .map(|name| store_positions(&code, name))
+        .collect();
+
+    vm::peephole::optimize(&mut code);
+
+    let lines = &code.debug.as_ref().unwrap().lines;
+    assert!(lines.windows(2).all(|w| w[0].pc < w[1].pc));
+    assert!(
+        lines
+            .iter()
+            .all(|e| (e.pc as usize) < code.instructions.len())
+    );
+    let after: Vec<_> = ["a", "b"]
+        .iter()
+        .map(|name| store_positions(&code, name))
+        .collect();
+    assert_eq!(before, after);
+}
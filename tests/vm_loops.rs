@@ -0,0 +1,138 @@
+mod common;
+
+use common::run_log;
+
+#[test]
+fn do_while_runs_the_body_before_the_condition() {
+    let log = run_log("var log = \"\";\ndo { log = log + \"x\"; } while (false);");
+
+    assert_eq!(log, "String(\"x\")");
+}
+
+#[test]
+fn do_while_continue_checks_the_condition() {
+    let log = run_log(
+        "var log = \"\";\nvar i = 0;\n\
+         do {\n\
+           i = i + 1;\n\
+           if (i == 2) continue;\n\
+           if (i == 4) break;\n\
+           log = log + i;\n\
+         } while (i < 10);\n\
+         log = log + \".\" + i;",
+    );
+
+    assert_eq!(log, "String(\"13.4\")");
+
+    // continue on the last iteration leaves the loop
+    let log = run_log(
+        "var log = \"\";\nvar i = 0;\n\
+         do { i = i + 1; continue; } while (i < 3);\n\
+         log = log + i;",
+    );
+    assert_eq!(log, "String(\"3\")");
+}
+
+#[test]
+fn labeled_break_and_continue_reach_the_outer_loop() {
+    let log = run_log(
+        "var log = \"\";\nvar i = 0;\n\
+         outer: while (i < 3) {\n\
+           i = i + 1;\n\
+           var j = 0;\n\
+           while (j < 3) {\n\
+             j = j + 1;\n\
+             if (j == 2) continue outer;\n\
+             if (i == 3) break outer;\n\
+             log = log + i + j;\n\
+           }\n\
+           log = log + \"never\";\n\
+         }",
+    );
+
+    assert_eq!(log, "String(\"1121\")");
+}
+
+#[test]
+fn break_leaves_a_labeled_block() {
+    let log = run_log(
+        "var log = \"\";\n\
+         done: { log = log + \"a\"; if (true) break done; log = log + \"b\"; }\n\
+         a: b: do { log = log + \"c\"; continue a; } while (false);",
+    );
+
+    assert_eq!(log, "String(\"ac\")");
+}
+
+#[test]
+#[should_panic(expected = "Undefined label 'nowhere'")]
+fn unknown_labels_are_compile_errors() {
+    run_log("while (true) { break nowhere; }");
+}
+
+#[test]
+#[should_panic(expected = "Continue target 'block' is not a loop")]
+fn continue_needs_a_labeled_loop() {
+    run_log("block: { while (true) { continue block; } }");
+}
+
+#[test]
+fn for_continue_runs_the_update() {
+    let log = run_log(
+        "var log = \"\";\n\
+         for (var i = 0; i < 5; i++) {\n\
+           if (i % 2 == 1) continue;\n\
+           log = log + i;\n\
+         }\n\
+         log = log + \".\" + i;",
+    );
+
+    assert_eq!(log, "String(\"024.5\")");
+}
+
+#[test]
+fn for_break_leaves_the_loop() {
+    let log = run_log(
+        "var log = \"\";\n\
+         for (var i = 0; ; i = i + 1) {\n\
+           if (i == 3) break;\n\
+           log = log + i;\n\
+         }\n\
+         log = log + \".\" + i;",
+    );
+
+    assert_eq!(log, "String(\"012.3\")");
+}
+
+#[test]
+fn for_inside_a_loop_keeps_its_own_targets() {
+    let log = run_log(
+        "var log = \"\";\nvar n = 0;\n\
+         while (n < 2) {\n\
+           n = n + 1;\n\
+           for (var i = 0; i < 3; i++) {\n\
+             if (i == 1) continue;\n\
+             if (i == 2) break;\n\
+             log = log + n + i;\n\
+           }\n\
+           log = log + \".\";\n\
+         }",
+    );
+
+    assert_eq!(log, "String(\"10.20.\")");
+}
+
+#[test]
+fn labeled_continue_runs_the_update_of_the_outer_for() {
+    let log = run_log(
+        "var log = \"\";\n\
+         outer: for (var i = 0; i < 3; i++) {\n\
+           for (var j = 0; j < 3; j++) {\n\
+             if (j > i) continue outer;\n\
+             log = log + i + j;\n\
+           }\n\
+         }",
+    );
+
+    assert_eq!(log, "String(\"001011202122\")");
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tinyjs::vm;
+use tinyjs::vm::error::ErrorKind;
+use tinyjs::vm::number::{number_to_radix_string, number_to_string, parse_float, string_to_number};
+use tinyjs::vm::object::Object;
+
+mod common;
+
+use common::{machine, run_log};
+
+// NaN compares equal to NaN, 0 and -0 are told apart
+fn same(a: f64, b: f64) -> bool {
+    (a.is_nan() && b.is_nan()) || (a == b && a.is_sign_negative() == b.is_sign_negative())
+}
+
+#[test]
+fn converts_strings_to_numbers() {
+    let table: &[(&str, f64)] = &[
+        // empty or white space only
+        ("", 0.0),
+        ("   ", 0.0),
+        ("\t\n\r\u{b}\u{c}", 0.0),
+        // decimal literals
+        ("42", 42.0),
+        (" 42 ", 42.0),
+        ("\n\t42\r\n", 42.0),
+        ("+42", 42.0),
+        ("-42", -42.0),
+        ("-0", -0.0),
+        ("+0", 0.0),
+        ("007", 7.0),
+        ("1.5", 1.5),
+        ("1.", 1.0),
+        (".5", 0.5),
+        ("-.5", -0.5),
+        ("1e3", 1000.0),
+        ("1E3", 1000.0),
+        ("1e+3", 1000.0),
+        ("1e-3", 0.001),
+        ("2.5e2", 250.0),
+        (".5e1", 5.0),
+        ("1.e1", 10.0),
+        ("0.1", 0.1),
+        ("1e400", f64::INFINITY),
+        ("-1e400", f64::NEG_INFINITY),
+        ("1e-400", 0.0),
+        ("9007199254740993", 9007199254740992.0),
+        // Infinity, only with this exact spelling
+        ("Infinity", f64::INFINITY),
+        ("+Infinity", f64::INFINITY),
+        ("-Infinity", f64::NEG_INFINITY),
+        (" Infinity ", f64::INFINITY),
+        ("infinity", f64::NAN),
+        ("inf", f64::NAN),
+        ("INFINITY", f64::NAN),
+        // hex integers
+        ("0x1F", 31.0),
+        ("0X1f", 31.0),
+        (" 0xff ", 255.0),
+        ("0x", f64::NAN),
+        ("0x1G", f64::NAN),
+        ("-0x10", f64::NAN),
+        ("0x1.5", f64::NAN),
+        // not a StringNumericLiteral
+        ("NaN", f64::NAN),
+        ("nan", f64::NAN),
+        (".", f64::NAN),
+        ("+", f64::NAN),
+        ("-", f64::NAN),
+        ("e5", f64::NAN),
+        ("1e", f64::NAN),
+        ("1e+", f64::NAN),
+        ("1 2", f64::NAN),
+        ("12px", f64::NAN),
+        ("1_000", f64::NAN),
+        ("--1", f64::NAN),
+        ("+-1", f64::NAN),
+        ("1..2", f64::NAN),
+        ("\u{a0}1", f64::NAN),
+    ];
+
+    for (input, expected) in table {
+        let actual = string_to_number(input);
+        assert!(
+            same(actual, *expected),
+            "ToNumber({:?}) = {}, expected {}",
+            input,
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn parse_float_reads_the_leading_number() {
+    let table: &[(&str, f64)] = &[
+        ("2.75", 2.75),
+        ("  2.75abc", 2.75),
+        ("-.5x", -0.5),
+        ("1e3e4", 1000.0),
+        ("1e", 1.0),
+        ("1e+", 1.0),
+        ("12px", 12.0),
+        ("Infinityx", f64::INFINITY),
+        ("-Infinity", f64::NEG_INFINITY),
+        ("0x10", 0.0),
+        ("1.2.3", 1.2),
+        ("-0", -0.0),
+        ("", f64::NAN),
+        ("   ", f64::NAN),
+        (".", f64::NAN),
+        ("abc", f64::NAN),
+        ("e5", f64::NAN),
+        ("x1", f64::NAN),
+    ];
+
+    for (input, expected) in table {
+        let actual = parse_float(input);
+        assert!(
+            same(actual, *expected),
+            "parseFloat({:?}) = {}, expected {}",
+            input,
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn arithmetic_converts_strings() {
+    let log = run_log("var log = \" 42 \" * 1 + (\"0x1F\" - 0) + (\"\" * 1) + (\"1e3\" / 1);");
+
+    assert_eq!(log, "Number(1073.0)");
+
+    let log = run_log("var log = \"\" + (\"inf\" * 1) + (\"Infinity\" * 1);");
+    assert_eq!(log, "String(\"NaNInfinity\")");
+}
+
+#[test]
+fn converts_numbers_to_strings() {
+    let table: &[(f64, &str)] = &[
+        (0.0, "0"),
+        (-0.0, "0"),
+        (f64::NAN, "NaN"),
+        (f64::INFINITY, "Infinity"),
+        (f64::NEG_INFINITY, "-Infinity"),
+        (1.0, "1"),
+        (-1.0, "-1"),
+        (1.5, "1.5"),
+        (-1.5, "-1.5"),
+        (0.1, "0.1"),
+        (0.1 + 0.2, "0.30000000000000004"),
+        (123456789.0, "123456789"),
+        (1e20, "100000000000000000000"),
+        (1e21, "1e+21"),
+        (1.5e21, "1.5e+21"),
+        (123e25, "1.23e+27"),
+        (0.000001, "0.000001"),
+        (0.0000012, "0.0000012"),
+        (1e-7, "1e-7"),
+        (1.5e-7, "1.5e-7"),
+        (-1e-7, "-1e-7"),
+        (5e-324, "5e-324"),
+        (f64::MAX, "1.7976931348623157e+308"),
+        (9007199254740993.0, "9007199254740992"),
+        (100.0, "100"),
+        (12.345, "12.345"),
+        (1.0 / 3.0, "0.3333333333333333"),
+    ];
+
+    for (input, expected) in table {
+        assert_eq!(number_to_string(*input), *expected, "ToString({:e})", input);
+    }
+}
+
+#[test]
+fn converts_numbers_to_other_radixes() {
+    let table: &[(f64, u32, &str)] = &[
+        (255.0, 16, "ff"),
+        (255.0, 2, "11111111"),
+        (-255.0, 16, "-ff"),
+        (35.0, 36, "z"),
+        (0.0, 2, "0"),
+        (0.5, 2, "0.1"),
+        (0.5, 16, "0.8"),
+        (3.75, 2, "11.11"),
+        (
+            0.1,
+            2,
+            "0.0001100110011001100110011001100110011001100110011001101",
+        ),
+        (0.1, 16, "0.1999999999999a"),
+        (1e21, 16, "3635c9adc5dea00000"),
+        (f64::NAN, 2, "NaN"),
+        (f64::NEG_INFINITY, 16, "-Infinity"),
+        (42.0, 10, "42"),
+    ];
+
+    for (input, radix, expected) in table {
+        assert_eq!(
+            number_to_radix_string(*input, *radix),
+            *expected,
+            "({}).toString({})",
+            input,
+            radix
+        );
+    }
+}
+
+#[test]
+fn prints_numbers_like_es1() {
+    let log = run_log("var log = \"\" + 1e21 + \",\" + -0 + \",\" + 0.000001 + \",\" + 1e-7;");
+
+    assert_eq!(log, "String(\"1e+21,0,0.000001,1e-7\")");
+}
+
+#[test]
+fn number_to_string_method() {
+    let log = run_log(
+        "var n = 255;\nvar log = n.toString(16) + \",\" + n.toString(2) + \",\" + n.toString()\n\
+         + \",\" + (0.5).toString(2) + \",\" + 1e21 + \",\" + -0;",
+    );
+
+    assert_eq!(log, "String(\"ff,11111111,255,0.1,1e+21,0\")");
+
+    let err = machine("var n = 1;\nn.toString(1);").run().unwrap_err();
+    assert_eq!(
+        err.summary(),
+        "RangeError: toString() radix must be between 2 and 36"
+    );
+}
+
+#[test]
+fn number_to_string_raises_typed_errors() {
+    for radix in ["1", "37"] {
+        let err = machine(&format!("var n = 255;\nn.toString({});", radix))
+            .run()
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::RangeError), "radix {}", radix);
+    }
+
+    // the method moved to an object that isn't a number
+    let mut machine = machine("o.f();");
+    let method = machine.number_prototype().borrow().get("toString").cloned();
+    let mut object = Object::new();
+    object.insert("f".to_string(), method.unwrap());
+    machine.set_global("o", vm::Value::Object(Rc::new(RefCell::new(object))));
+
+    let err = machine.run().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::TypeError), "{}", err);
+}
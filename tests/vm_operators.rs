@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tinyjs::vm;
+use tinyjs::vm::object::Object;
+
+mod common;
+
+use common::{log_of, machine, run_log};
+
+fn native(_: &mut vm::VM, _: &[vm::Value]) -> vm::NativeResult {
+    Ok(vm::Value::Undefined)
+}
+
+#[test]
+fn typeof_returns_the_es1_type_names() {
+    let mut machine = machine(
+        "function f() {}\nvar log = typeof undefined + \",\" + typeof null + \",\" + typeof true\n\
+         + \",\" + typeof 1 + \",\" + typeof \"s\" + \",\" + typeof {} + \",\" + typeof [1]\n\
+         + \",\" + typeof f + \",\" + typeof native;",
+    );
+    machine.set_global("native", vm::Value::NativeFunction(native));
+
+    assert_eq!(
+        log_of(machine),
+        "String(\"undefined,object,boolean,number,string,object,object,function,function\")"
+    );
+}
+
+#[test]
+fn typeof_undeclared_variables_is_undefined() {
+    let log =
+        run_log("var log = typeof nothing;\nvar x = 1;\nlog = log + typeof x + typeof (x + \"\");");
+
+    assert_eq!(log, "String(\"undefinednumberstring\")");
+}
+
+#[test]
+fn in_looks_up_the_prototype_chain() {
+    let mut prototype = Object::new();
+    prototype.insert("inherited".to_string(), vm::Value::Null);
+    let mut machine = machine(
+        "var o = {a: 1};\nvar log = \"\" + (\"a\" in o) + (\"b\" in o) + (0 in [5])\n\
+         + (1 in [5]) + (\"PI\" in Math) + (\"inherited\" in p);\n\
+         delete o.a;\nlog = log + (\"a\" in o);",
+    );
+    machine.set_global(
+        "p",
+        vm::Value::Object(Rc::new(RefCell::new(Object::with_prototype(Rc::new(
+            RefCell::new(prototype),
+        ))))),
+    );
+
+    assert_eq!(
+        log_of(machine),
+        "String(\"truefalsetruefalsetruetruefalse\")"
+    );
+}
+
+#[test]
+fn in_needs_an_object() {
+    let err = machine("var r = \"a\" in 5;").run().unwrap_err();
+
+    assert_eq!(
+        err.summary(),
+        "TypeError: cannot use 'in' to search for 'a' in 5"
+    );
+}
+
+#[test]
+fn equality_converts_the_operands() {
+    let log = run_log(
+        "var log = \"\" + (1 == \"1\") + (\"0x10\" == 16) + (true == 1) + (false == \"\")\n\
+         + (null == undefined) + (null == 0) + (undefined == false) + (\"a\" == \"b\")\n\
+         + (0 / 0 == 0 / 0) + (0 == -0) + (2 != \"2\");",
+    );
+
+    assert_eq!(
+        log,
+        "String(\"truetruetruetruetruefalsefalsefalsefalsetruefalse\")"
+    );
+}
+
+#[test]
+fn objects_and_functions_are_equal_to_themselves_only() {
+    let log = run_log(
+        "function f() {}\nfunction g() {}\nvar o = {};\nvar p = o;\n\
+         var log = \"\" + (o == p) + (o == {}) + ({} == {}) + (f == f) + (f == g)\n\
+         + (o == \"[object Object]\") + (o != 1) + (o == null);",
+    );
+
+    assert_eq!(log, "String(\"truefalsefalsetruefalsetruetruefalse\")");
+}
+
+#[test]
+fn relational_operators_compare_strings_and_numbers() {
+    let log = run_log(
+        "var log = \"\" + (\"a\" < \"b\") + (\"ab\" < \"abc\") + (\"B\" < \"a\") + (\"10\" < \"9\")\n\
+         + (10 < \"9\") + (\"b\" > \"a\") + (\"a\" <= \"a\") + (\"a\" >= \"b\") + (null >= 0)\n\
+         + (1 < 0 / 0) + (1 >= 0 / 0) + (\"x\" <= 1) + (\"x\" > 1);",
+    );
+
+    assert_eq!(
+        log,
+        "String(\"truetruetruetruefalsetruetruefalsetruefalsefalsefalsefalse\")"
+    );
+}
+
+#[test]
+fn operators_convert_objects_with_value_of() {
+    let log = run_log(
+        "function v() { return 42; }\nvar o = {valueOf: v};\n\
+         var log = \"\" + (o + 1) + \",\" + o * 2 + \",\" + (o - 2) + \",\" + (o == 42) + \",\"\n\
+         + (o < 50) + \",\" + -o;",
+    );
+
+    assert_eq!(log, "String(\"43,84,40,true,true,-42\")");
+}
+
+#[test]
+fn to_string_converts_objects_without_value_of() {
+    let log = run_log(
+        "function s() { return \"key\"; }\nfunction v() { return 42; }\n\
+         function itself() { return {}; }\nvar t = {toString: s};\nvar o = {key: 1};\n\
+         var log = \"\" + t + (t == \"key\") + (t in o) + ({valueOf: v, toString: s} + \"\")\n\
+         + ({valueOf: itself, toString: s} + \"\") + {};",
+    );
+
+    assert_eq!(log, "String(\"keytruetrue42key[object Object]\")");
+}
+
+#[test]
+fn conversion_methods_can_use_conversions() {
+    let log = run_log(
+        "function inner() { return 1; }\nvar a = {valueOf: inner};\n\
+         function outer() { return a + 10; }\nvar b = {valueOf: outer};\n\
+         function careful() { try { throw 1; } catch (e) { return 7; } }\n\
+         var log = \"\" + b * 2 + \",\" + {valueOf: careful} * 2;",
+    );
+
+    assert_eq!(log, "String(\"22,14\")");
+}
+
+#[test]
+fn exceptions_of_conversion_methods_reach_the_script() {
+    let log = run_log(
+        "function bad() { throw \"boom\"; }\nvar log = \"\";\n\
+         try { log = {valueOf: bad} + 1; } catch (e) { log = \"caught \" + e; }",
+    );
+
+    assert_eq!(log, "String(\"caught boom\")");
+}
+
+#[test]
+fn endless_conversions_are_a_range_error() {
+    let log = run_log(
+        "var o = {};\nfunction again() { return o + 1; }\no.valueOf = again;\nvar log = \"\";\n\
+         try { log = o + 1; } catch (e) { log = e.name + \": \" + e.message; }\n\
+         log = log + \",\" + (o.valueOf == again);",
+    );
+
+    assert_eq!(
+        log,
+        "String(\"RangeError: Maximum call stack size exceeded,true\")"
+    );
+}
+
+#[test]
+fn objects_without_a_primitive_value_are_a_type_error() {
+    let err = machine("function itself() { return {}; }\nvar o = {valueOf: itself, toString: itself};\nvar r = o + 1;")
+        .run()
+        .unwrap_err();
+
+    assert_eq!(
+        err.summary(),
+        "TypeError: cannot convert object to primitive value"
+    );
+}
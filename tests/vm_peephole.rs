@@ -0,0 +1,273 @@
+use tinyjs::ir::Const;
+use tinyjs::vm;
+use tinyjs::vm::OpCode;
+
+mod common;
+
+use common::compile;
+
+// indices in the pools built by `bytecode`
+const A: u32 = 0;
+const X: u32 = 1;
+const Y: u32 = 2;
+const ONE: u32 = 0;
+const TWO: u32 = 1;
+const TRUE: u32 = 2;
+
+fn bytecode(instructions: Vec<OpCode>) -> vm::Bytecode {
+    let mut code = vm::Bytecode::new();
+    code.names = ["a", "x", "y"].map(String::from).to_vec();
+    code.constants = vec![Const::Number(1.0), Const::Number(2.0), Const::Boolean(true)];
+    code.instructions = instructions;
+    code
+}
+
+fn assert_same_results(source: &str, names: &[&str]) {
+    let mut original = vm::VM::new(compile(source));
+    original.run().unwrap();
+
+    let mut code = compile(source);
+    let before = code.instructions.len();
+    let removed = vm::peephole::optimize(&mut code);
+    assert!(removed > 0);
+    assert_eq!(code.instructions.len(), before - removed);
+
+    let mut optimized = vm::VM::new(code);
+    optimized.run().unwrap();
+
+    for name in names {
+        assert_eq!(
+            format!("{:?}", original.get_variable(name)),
+            format!("{:?}", optimized.get_variable(name)),
+            "different value for '{}'",
+            name
+        );
+    }
+}
+
+#[test]
+fn store_then_load_becomes_dup() {
+    let mut code = bytecode(vec![
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Load(X),
+        OpCode::Store(Y),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(
+        code.instructions,
+        vec![
+            OpCode::Const(ONE),
+            OpCode::Dup,
+            OpCode::Store(X),
+            OpCode::Store(Y),
+            OpCode::Halt,
+        ]
+    );
+}
+
+#[test]
+fn folds_constant_conditions() {
+    let mut code = bytecode(vec![
+        OpCode::Const(TRUE),
+        OpCode::Not,
+        OpCode::JumpIf(3),
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(
+        code.instructions,
+        vec![OpCode::Const(ONE), OpCode::Store(X), OpCode::Halt,]
+    );
+}
+
+#[test]
+fn not_before_conditional_jump_inverts_it() {
+    let mut code = bytecode(vec![
+        OpCode::Load(A),
+        OpCode::Not,
+        OpCode::JumpIf(3),
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(code.instructions[1], OpCode::JumpIfNot(3));
+    assert_eq!(code.instructions.len(), 5);
+}
+
+#[test]
+fn threads_jumps_and_fixes_offsets() {
+    let mut code = bytecode(vec![
+        OpCode::Load(A),
+        OpCode::JumpIf(3), // -> 4
+        OpCode::Nop,
+        OpCode::Nop,
+        OpCode::Jump(2), // -> 6
+        OpCode::Const(ONE),
+        OpCode::Const(TWO),
+        OpCode::Store(X),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(
+        code.instructions,
+        vec![
+            OpCode::Load(A),
+            OpCode::JumpIf(3),
+            OpCode::Jump(2),
+            OpCode::Const(ONE),
+            OpCode::Const(TWO),
+            OpCode::Store(X),
+            OpCode::Halt,
+        ]
+    );
+}
+
+#[test]
+fn removes_overwritten_stores() {
+    let mut code = compile("var b; b = 5;");
+    let before = code.instructions.len();
+
+    vm::peephole::optimize(&mut code);
+
+    assert!(code.instructions.len() < before);
+    assert!(!code.instructions.iter().any(
+        |op| matches!(op, OpCode::Const(c) if code.constants[*c as usize] == Const::Undefined)
+    ));
+}
+
+#[test]
+fn keeps_windows_across_jump_targets() {
+    // the Load is a jump target, it can't become a Dup
+    let mut code = bytecode(vec![
+        OpCode::Load(A),
+        OpCode::JumpIf(3),
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Load(X),
+        OpCode::Store(Y),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(code.instructions.len(), 7);
+    assert_eq!(code.instructions[4], OpCode::Load(X));
+}
+
+#[test]
+fn keeps_windows_with_a_jump_target_between_their_instructions() {
+    // the branch lands on the second Nop, between the Store and the Load
+    let mut code = bytecode(vec![
+        OpCode::Load(A),
+        OpCode::JumpIf(4),
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Nop,
+        OpCode::Nop,
+        OpCode::Load(X),
+        OpCode::Store(Y),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert!(!code.instructions.contains(&OpCode::Dup));
+    let OpCode::JumpIf(offset) = code.instructions[1] else {
+        panic!("the branch went away: {:?}", code.instructions);
+    };
+    assert_eq!(code.instructions[(1 + offset) as usize], OpCode::Load(X));
+}
+
+#[test]
+fn preserves_program_results() {
+    assert_same_results(
+        "var i = 0; var s = 0; while (i < 10) { i++; if (i == 3) { continue; } if (i == 7) { break; } s += i; }",
+        &["i", "s"],
+    );
+    assert_same_results(
+        "var a = 4; var b = a > 2 ? a * 2 : a - 1; var c = !(a == 4); if (!c) { b = 0; }",
+        &["a", "b", "c"],
+    );
+    assert_same_results(
+        "var o = { x: 1 }; var k; var n = 0; for (k in o) { n = n + 1; }",
+        &["k", "n"],
+    );
+}
+
+#[test]
+fn drops_the_unread_stores_of_temporaries_only() {
+    let mut code = bytecode(vec![
+        OpCode::Const(ONE),
+        OpCode::Store(3),
+        OpCode::Const(TWO),
+        OpCode::Store(X),
+        OpCode::Halt,
+    ]);
+    // named like the temporaries of the compiler, but not flagged as one
+    code.names.push("__t1".to_string());
+    code.temporaries.insert(X);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(
+        code.instructions,
+        vec![OpCode::Const(ONE), OpCode::Store(3), OpCode::Halt]
+    );
+}
+
+#[test]
+fn keeps_stores_followed_by_instructions_that_can_raise() {
+    for raising in [
+        vec![OpCode::Load(Y)],
+        vec![OpCode::Const(ONE), OpCode::Throw],
+    ] {
+        let mut instructions = vec![OpCode::Const(ONE), OpCode::Store(X)];
+        instructions.extend(raising);
+        instructions.extend([OpCode::Const(TWO), OpCode::Store(X), OpCode::Halt]);
+        let mut code = bytecode(instructions);
+
+        vm::peephole::optimize(&mut code);
+
+        // the host sees the value stored before the error
+        let mut machine = vm::VM::new(code);
+        assert!(machine.run().is_err());
+        assert_eq!(format!("{:?}", machine.get_variable("x")), "Number(1.0)");
+    }
+}
+
+#[test]
+fn removes_stores_overwritten_after_instructions_that_cant_raise() {
+    let mut code = bytecode(vec![
+        OpCode::Const(ONE),
+        OpCode::Store(X),
+        OpCode::Const(TRUE),
+        OpCode::Not,
+        OpCode::Store(Y),
+        OpCode::Const(TWO),
+        OpCode::Store(X),
+        OpCode::Halt,
+    ]);
+
+    vm::peephole::optimize(&mut code);
+
+    assert_eq!(
+        code.instructions
+            .iter()
+            .filter(|op| **op == OpCode::Store(X))
+            .count(),
+        1
+    );
+}
@@ -0,0 +1,180 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tinyjs::ir::Const;
+use tinyjs::vm;
+use tinyjs::vm::OpCode;
+use tinyjs::vm::object::{Attributes, Object};
+
+mod common;
+
+use common::{machine, run_log};
+
+fn number(object: &Object, key: &str) -> Option<f64> {
+    match object.get(key) {
+        Some(vm::Value::Number(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+#[test]
+fn read_only_properties_ignore_the_writes_of_the_script() {
+    let mut object = Object::new();
+    object.define(
+        "pi".to_string(),
+        vm::Value::Number(3.0),
+        Attributes::CONSTANT,
+    );
+    object.put("pi".to_string(), vm::Value::Number(4.0));
+    assert_eq!(number(&object, "pi"), Some(3.0));
+
+    // the host can still change it
+    object.insert("pi".to_string(), vm::Value::Number(5.0));
+    assert_eq!(number(&object, "pi"), Some(5.0));
+    assert_eq!(object.attributes("pi"), Some(Attributes::CONSTANT));
+}
+
+#[test]
+fn inherited_read_only_properties_are_not_shadowed() {
+    let mut prototype = Object::new();
+    prototype.define(
+        "x".to_string(),
+        vm::Value::Number(1.0),
+        Attributes::CONSTANT,
+    );
+    prototype.insert("y".to_string(), vm::Value::Number(1.0));
+    let mut object = Object::with_prototype(Rc::new(RefCell::new(prototype)));
+
+    object.put("x".to_string(), vm::Value::Number(2.0));
+    object.put("y".to_string(), vm::Value::Number(2.0));
+
+    assert!(!object.contains_key("x"));
+    assert_eq!(number(&object, "y"), Some(2.0));
+}
+
+#[test]
+fn dont_delete_properties_stay() {
+    let mut object = Object::new();
+    object.define("kept".to_string(), vm::Value::Null, Attributes::CONSTANT);
+    object.insert("gone".to_string(), vm::Value::Null);
+
+    assert!(!object.delete("kept"));
+    assert!(object.delete("gone"));
+    assert!(object.delete("missing"));
+    assert!(object.contains_key("kept"));
+    assert!(!object.contains_key("gone"));
+}
+
+#[test]
+fn math_constants_are_read_only() {
+    let mut code = vm::Bytecode::new();
+    code.names = vec!["Math".to_string()];
+    code.constants = vec![Const::String("PI".to_string()), Const::Number(4.0)];
+    code.instructions = vec![
+        OpCode::Load(0),
+        OpCode::Const(0),
+        OpCode::Const(1),
+        OpCode::SetProp,
+        OpCode::Load(0),
+        OpCode::Const(0),
+        OpCode::GetProp,
+        OpCode::Halt,
+    ];
+
+    let result = vm::VM::new(code).run().unwrap();
+    assert_eq!(format!("{:?}", result), "Number(3.141592653589793)");
+}
+
+#[test]
+fn for_in_skips_dont_enum_properties() {
+    let log = run_log("var log = \"\";\nvar k;\nfor (k in Math) log = log + k;");
+    assert_eq!(log, "String(\"\")");
+
+    let log = run_log(
+        "var log = \"\";\nvar k;\n\
+         try { missing(); } catch (e) { for (k in e) log = log + k + \",\"; }",
+    );
+    assert_eq!(log, "String(\"name,message,\")");
+}
+
+#[test]
+fn prototype_constructor_is_dont_enum() {
+    let log = run_log(
+        "function F() {}\nfunction G() {}\nF.prototype.x = 1;\nvar log = \"\";\nvar k;\n\
+         for (k in F.prototype) log = log + k + \",\";\n\
+         log = log + (\"constructor\" in F.prototype) + (F.prototype.constructor == F)\n\
+         + (G.prototype.constructor == F) + G.prototype.x;\n\
+         G.prototype = {y: 2};\nlog = log + G.prototype.y;",
+    );
+
+    assert_eq!(log, "String(\"x,truetruefalseundefined2\")");
+}
+
+#[test]
+fn delete_removes_the_property_from_the_object() {
+    let log = run_log(
+        "var o = {a: 1, b: 2, c: 3};\nvar k = \"c\";\n\
+         var log = \"\" + delete o.a + delete o[k] + delete o.missing + \":\";\n\
+         for (k in o) log = log + k;",
+    );
+
+    assert_eq!(log, "String(\"truetruetrue:b\")");
+}
+
+#[test]
+fn member_writes_are_seen_through_every_reference() {
+    let log = run_log(
+        "var o = {};\nvar p = o;\nvar k = \"b\";\n\
+         o.a = 5;\np[k] = o.a + 1;\no.a += 10;\no.c = {};\no.c.d = \"x\";\n\
+         var log = \"\" + p.a + \",\" + o.b + \",\" + p.c.d + \",\" + (\"a\" in p);",
+    );
+
+    assert_eq!(log, "String(\"15,6,x,true\")");
+}
+
+#[test]
+fn member_reads_after_delete_are_undefined() {
+    let log = run_log(
+        "var o = {};\no.a = 5;\nvar r1 = \"a\" in o;\nvar r2 = delete o.a;\n\
+         var log = \"\" + r1 + r2 + (\"a\" in o) + \",\" + o.a;",
+    );
+
+    assert_eq!(log, "String(\"truetruefalse,undefined\")");
+}
+
+#[test]
+fn delete_respects_dont_delete() {
+    let mut machine = machine("var log = \"\" + delete Math.PI;");
+    machine.run().unwrap();
+
+    assert_eq!(
+        format!("{:?}", machine.get_variable("log")),
+        "String(\"false\")"
+    );
+    let vm::Value::Object(math) = machine.get_variable("Math") else {
+        panic!("Math is not an object");
+    };
+    assert!(math.borrow().contains_key("PI"));
+}
+
+#[test]
+fn delete_on_identifiers() {
+    let log = run_log(
+        "var x = 1;\nvar o = {a: 1};\nvar log = \"\" + delete x + delete undeclared;\n\
+         with (o) { log = log + delete a; }\n\
+         var k;\nfor (k in o) log = log + k;",
+    );
+
+    // declared variables stay, the property of the with object goes away
+    assert_eq!(log, "String(\"falsetruetrue\")");
+}
+
+#[test]
+fn delete_on_null_is_a_type_error() {
+    let err = machine("var o = null;\ndelete o.a;").run().unwrap_err();
+
+    assert_eq!(
+        err.summary(),
+        "TypeError: cannot delete property 'a' of null"
+    );
+}
@@ -0,0 +1,127 @@
+use tinyjs::vm;
+use tinyjs::vm::OpCode;
+use tinyjs::vm::error::{ErrorKind, RuntimeError};
+
+mod common;
+
+use common::compile;
+
+fn out_of_range(_: &mut vm::VM, _: &[vm::Value]) -> vm::NativeResult {
+    Err((ErrorKind::RangeError, "out of range".to_string()))
+}
+
+fn run_error(source: &str) -> RuntimeError {
+    vm::VM::new(compile(source))
+        .run()
+        .expect_err("the program should fail")
+}
+
+#[test]
+fn calling_a_non_function_is_a_type_error() {
+    let err = run_error("var a = 1;\na();");
+
+    assert_eq!(err.kind(), Some(ErrorKind::TypeError));
+    assert_eq!(err.message(), "a is not a function");
+    assert_eq!(
+        err.to_string(),
+        "Uncaught TypeError: a is not a function\n    at 2:1"
+    );
+
+    let err = run_error("var o = {};\no.f();");
+    assert_eq!(err.summary(), "TypeError: undefined is not a function");
+}
+
+#[test]
+fn reading_an_undeclared_variable_is_a_reference_error() {
+    let err = run_error("var a = 1;\nvar b = a + c;");
+
+    assert_eq!(err.kind(), Some(ErrorKind::ReferenceError));
+    assert_eq!(err.message(), "c is not defined");
+
+    let err = run_error("missing();");
+    assert_eq!(err.summary(), "ReferenceError: missing is not defined");
+}
+
+#[test]
+fn properties_are_not_looked_up_as_variables() {
+    let mut machine = vm::VM::new(compile("var o = {a: 1};\nvar y = o.a;\nvar z = o.b;"));
+    machine.run().unwrap();
+
+    assert_eq!(format!("{:?}", machine.get_variable("y")), "Number(1.0)");
+    assert_eq!(format!("{:?}", machine.get_variable("z")), "Undefined");
+}
+
+#[test]
+fn natives_choose_the_kind_of_their_errors() {
+    let mut machine = vm::VM::new(compile(
+        "var log;\ntry { check(); } catch (e) { log = e; }\ncheck();",
+    ));
+    machine.set_global("check", vm::Value::NativeFunction(out_of_range));
+
+    let err = machine.run().expect_err("the program should fail");
+    assert_eq!(err.kind(), Some(ErrorKind::RangeError));
+    assert_eq!(
+        err.to_string(),
+        "Uncaught RangeError: out of range\n    at 3:1"
+    );
+    // caught, it is an error object like the ones raised by the VM
+    assert!(format!("{:?}", machine.get_variable("log")).contains("String(\"RangeError\")"));
+}
+
+#[test]
+fn traces_the_calls_leading_to_the_error() {
+    let mut code = compile("function f() {\n  g();\n}\nfunction h() {\n  f();\n}\nh();\n");
+    code.debug.as_mut().unwrap().file = "test.js".to_string();
+
+    let err = vm::VM::new(code).run().unwrap_err();
+
+    let functions: Vec<Option<&str>> = err
+        .stack
+        .iter()
+        .map(|frame| frame.function.as_deref())
+        .collect();
+    assert_eq!(functions, vec![Some("f"), Some("h"), None]);
+    assert_eq!(
+        err.to_string(),
+        "Uncaught ReferenceError: g is not defined\n    at f (test.js:2:3)\n    at h (test.js:5:3)\n    at test.js:7:1"
+    );
+}
+
+#[test]
+fn errors_are_objects_with_name_message_and_stack() {
+    let err = run_error("function f() {\n  null_value();\n}\nf();");
+
+    let vm::Value::Object(object) = &err.value else {
+        panic!("not an object: {:?}", err.value);
+    };
+    let object = object.borrow();
+    let property = |key: &str| format!("{:?}", object.get(key));
+    assert_eq!(property("name"), "Some(String(\"ReferenceError\"))");
+    assert_eq!(
+        property("message"),
+        "Some(String(\"null_value is not defined\"))"
+    );
+    assert_eq!(
+        property("stack"),
+        format!("Some(String({:?}))", err.trace())
+    );
+}
+
+#[test]
+fn binds_parameters_and_missing_arguments() {
+    let mut machine = vm::VM::new(compile("function f(a, b) { var c = a + b; } f(1);"));
+
+    assert!(machine.run().is_ok());
+}
+
+#[test]
+fn broken_bytecode_is_an_internal_error() {
+    let mut code = vm::Bytecode::new();
+    code.instructions = vec![OpCode::Pop, OpCode::Add, OpCode::Halt];
+
+    let err = vm::VM::new(code).run().unwrap_err();
+
+    assert_eq!(err.kind(), Some(ErrorKind::InternalError));
+    assert_eq!(err.message(), "stack underflow");
+    assert_eq!(err.stack[0].pc, 1);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::run_log;
+
+#[test]
+fn jumps_to_the_matching_case_and_falls_through() {
+    let log = run_log(
+        "var log = \"\";\n\
+         switch (2) { case 1: log = log + \"a\"; case 2: log = log + \"b\"; case 3: log = log + \"c\"; }",
+    );
+
+    assert_eq!(log, "String(\"bc\")");
+}
+
+#[test]
+fn break_leaves_the_switch() {
+    let log = run_log(
+        "var log = \"\";\n\
+         switch (\"x\") { case \"x\": log = log + \"x\"; break; case \"y\": log = log + \"y\"; }\n\
+         log = log + \".\";",
+    );
+
+    assert_eq!(log, "String(\"x.\")");
+}
+
+#[test]
+fn default_is_taken_when_nothing_matches_wherever_it_is() {
+    let log = run_log(
+        "var log = \"\";\n\
+         switch (9) { case 1: log = log + \"a\"; default: log = log + \"d\"; case 2: log = log + \"b\"; }\n\
+         switch (9) { case 1: log = log + \"a\"; }",
+    );
+
+    assert_eq!(log, "String(\"db\")");
+}
+
+#[test]
+fn continue_goes_to_the_enclosing_loop() {
+    let log = run_log(
+        "var log = \"\";\nvar i = 0;\n\
+         while (i < 4) {\n\
+           i = i + 1;\n\
+           switch (i % 2) { case 0: continue; default: log = log + i; }\n\
+           log = log + \".\";\n\
+         }",
+    );
+
+    assert_eq!(log, "String(\"1.3.\")");
+}
+
+#[test]
+fn evaluates_the_discriminant_once() {
+    let log = run_log(
+        "var log = \"\";\nvar n = 0;\n\
+         switch (n = n + 1) { case 0: log = \"zero\"; break; case 1: log = \"one\"; break; }\n\
+         log = log + n;",
+    );
+
+    assert_eq!(log, "String(\"one1\")");
+}
+
+#[test]
+fn cases_match_without_conversion() {
+    let log = run_log(
+        "var log = \"\";\n\
+         switch (1) { case \"1\": log = log + \"string\"; break; case true: log = log + \"bool\"; break; case 1: log = log + \"number\"; }",
+    );
+
+    assert_eq!(log, "String(\"number\")");
+}
@@ -0,0 +1,198 @@
+use tinyjs::ir::Const;
+use tinyjs::vm;
+use tinyjs::vm::OpCode;
+use tinyjs::vm::verify::verify;
+
+mod common;
+
+use common::compile;
+
+// indices in the pools built by `bytecode`
+const A: u32 = 0;
+const B: u32 = 1;
+const C: u32 = 2;
+const F: u32 = 3;
+const X: u32 = 4;
+const ONE: u32 = 0;
+const NULL: u32 = 1;
+
+fn bytecode(instructions: Vec<OpCode>) -> vm::Bytecode {
+    let mut code = vm::Bytecode::new();
+    code.names = ["a", "b", "c", "f", "x"].map(String::from).to_vec();
+    code.constants = vec![Const::Number(1.0), Const::Null];
+    code.instructions = instructions;
+    code
+}
+
+fn messages(code: Vec<OpCode>) -> Vec<String> {
+    verify(&bytecode(code))
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+#[test]
+fn accepts_compiler_output() {
+    for source in [
+        "var i = 0; while (i < 10) { i++; if (i == 3) { continue; } if (i == 7) { break; } }",
+        "function f(a, b) { if (a) { return b; } return a; } f(1, 2); f(3, 4);",
+        "var o = { a: 1, b: [1, 2] }; var k; for (k in o) { o.a = o.a + 1; }",
+        "with (o) { a = 2; } var c = a > 1 ? 'yes' : 'no';",
+    ] {
+        let mut code = compile(source);
+        assert_eq!(verify(&code), vec![], "rejected '{}'", source);
+
+        vm::peephole::optimize(&mut code);
+        assert_eq!(verify(&code), vec![], "rejected optimized '{}'", source);
+    }
+}
+
+#[test]
+fn rejects_jumps_out_of_range() {
+    assert_eq!(
+        messages(vec![OpCode::Jump(5), OpCode::Halt]),
+        vec!["0000: jump target 5 is out of range"]
+    );
+    assert_eq!(
+        messages(vec![OpCode::Nop, OpCode::Jump(-2), OpCode::Halt]),
+        vec!["0001: jump target -1 is out of range"]
+    );
+}
+
+#[test]
+fn rejects_stack_underflow() {
+    assert_eq!(
+        messages(vec![OpCode::Const(ONE), OpCode::Swap, OpCode::Halt]),
+        vec!["0001: stack underflow: Swap needs 2 values but the stack may hold 1"]
+    );
+
+    // the path jumping over `Load b` reaches the Store with an empty stack
+    let errors = messages(vec![
+        OpCode::Load(A),
+        OpCode::JumpIf(2),
+        OpCode::Load(B),
+        OpCode::Store(C),
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        errors,
+        vec!["0003: stack underflow: Store c needs 1 values but the stack may hold 0"]
+    );
+}
+
+#[test]
+fn rejects_pool_indices_out_of_range() {
+    assert_eq!(
+        messages(vec![OpCode::Const(7), OpCode::Store(9), OpCode::Halt]),
+        vec![
+            "0000: constant index 7 is out of range",
+            "0001: name index 9 is out of range"
+        ]
+    );
+}
+
+#[test]
+fn rejects_running_past_the_end() {
+    assert_eq!(
+        messages(vec![OpCode::Const(NULL)]),
+        vec!["0000: execution runs past the end of the code"]
+    );
+    assert_eq!(messages(vec![]), vec!["0000: empty program"]);
+}
+
+#[test]
+fn checks_function_bodies() {
+    let body_out_of_range = messages(vec![
+        OpCode::FnStart {
+            name: F,
+            argc: 0,
+            body_offset: 9,
+        },
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        body_out_of_range,
+        vec!["0000: body of function 'f' is out of range"]
+    );
+
+    // the VM would look for the FnEnd until the end of the code
+    let missing_fn_end = messages(vec![
+        OpCode::FnStart {
+            name: F,
+            argc: 0,
+            body_offset: 2,
+        },
+        OpCode::Return { has_value: false },
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        missing_fn_end,
+        vec!["0000: function 'f' is declared here but its body doesn't end with FnEnd"]
+    );
+
+    let falls_through = messages(vec![
+        OpCode::Jump(3),
+        OpCode::FnStart {
+            name: F,
+            argc: 0,
+            body_offset: 2,
+        },
+        OpCode::Const(NULL),
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        falls_through,
+        vec!["0002: execution runs past the end of function 'f'"]
+    );
+
+    let jumps_into_body = messages(vec![
+        OpCode::Jump(2),
+        OpCode::FnStart {
+            name: F,
+            argc: 0,
+            body_offset: 2,
+        },
+        OpCode::Return { has_value: false },
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        jumps_into_body,
+        vec!["0000: jump to 0002 crosses a function boundary"]
+    );
+
+    // the VM would resume after the first FnEnd, at 0003
+    let wrong_fn_end = messages(vec![
+        OpCode::FnStart {
+            name: F,
+            argc: 0,
+            body_offset: 4,
+        },
+        OpCode::Return { has_value: false },
+        OpCode::FnEnd,
+        OpCode::FnEnd,
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        wrong_fn_end,
+        vec!["0000: body of function 'f' ends at 0003 but the VM skips it to another FnEnd"]
+    );
+}
+
+#[test]
+fn accepts_declared_functions_with_fn_end() {
+    let code = vec![
+        OpCode::FnStart {
+            name: F,
+            argc: 1,
+            body_offset: 4,
+        },
+        OpCode::Load(A),
+        OpCode::Return { has_value: true },
+        OpCode::FnEnd,
+        OpCode::Const(ONE),
+        OpCode::Call { name: F, argc: 1 },
+        OpCode::Store(X),
+        OpCode::Halt,
+    ];
+    assert_eq!(messages(code), Vec::<String>::new());
+}